@@ -0,0 +1,38 @@
+use std::{collections::HashMap, sync::Mutex};
+
+use buttplug::client::ButtplugClientDevice;
+use once_cell::sync::Lazy;
+
+/// Last known battery level of each device by device index, from 0.0 to 1.0
+static BATTERY_LEVELS: Lazy<Mutex<HashMap<u32, f64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Read and store the battery level of a device, if it reports one
+pub async fn update_battery_level(dev: &ButtplugClientDevice) -> Option<f64> {
+    if !dev.has_battery_level() {
+        return None;
+    }
+
+    match dev.battery_level().await {
+        Ok(level) => {
+            if let Ok(mut levels) = BATTERY_LEVELS.lock() {
+                levels.insert(dev.index(), level);
+            }
+            Some(level)
+        }
+        Err(e) => {
+            log::debug!("error reading battery level of {}: {e}", dev.name());
+            None
+        }
+    }
+}
+
+/// Last known battery level of a device, `None` if it doesn't report one
+pub fn battery_level(dev: &ButtplugClientDevice) -> Option<f64> {
+    BATTERY_LEVELS.lock().ok()?.get(&dev.index()).copied()
+}
+
+pub fn forget_device(dev: &ButtplugClientDevice) {
+    if let Ok(mut levels) = BATTERY_LEVELS.lock() {
+        levels.remove(&dev.index());
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use buttplug::{
+    client::ButtplugClient,
+    core::{
+        connector::{
+            ButtplugInProcessClientConnector, ButtplugInProcessClientConnectorBuilder,
+            ButtplugRemoteClientConnector, ButtplugWebsocketClientTransport,
+        },
+        message::serializer::ButtplugClientJSONSerializer,
+    },
+    server::{
+        device::hardware::communication::{
+            btleplug::BtlePlugCommunicationManagerBuilder,
+            lovense_dongle::LovenseHIDDongleCommunicationManagerBuilder,
+            xinput::XInputDeviceCommunicationManagerBuilder,
+        },
+        ButtplugServer, ButtplugServerBuilder, ButtplugServerError,
+    },
+};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Deserialize;
+use tokio::time::sleep;
+
+/// Delay before the second reconnect attempt, doubling after every failed attempt up to `MAX_RECONNECT_DELAY`
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Set while a reconnect is running so disconnect events and commands don't start another one
+static RECONNECTING: AtomicBool = AtomicBool::new(false);
+/// Set while disconnected on purpose because the game has been idle
+static IDLE: AtomicBool = AtomicBool::new(false);
+
+/// Which buttplug server the client talks to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionKind {
+    /// A server running inside the game process, managing devices itself
+    InProcess,
+    /// An already running Intiface Central, using its device config and plugins
+    Intiface,
+}
+
+/// Which device managers the in-process server starts
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default)]
+pub struct CommManagers {
+    btleplug: bool,
+    lovense_dongle: bool,
+    /// Claims xbox compatible controllers as devices, which can get in the way of playing with one
+    xinput: bool,
+}
+
+impl Default for CommManagers {
+    fn default() -> Self {
+        Self {
+            btleplug: true,
+            lovense_dongle: true,
+            xinput: true,
+        }
+    }
+}
+
+impl CommManagers {
+    /// Every manager that is turned on, with the name it has in the config
+    fn enabled(self) -> Vec<(&'static str, CommManagers)> {
+        let none = CommManagers {
+            btleplug: false,
+            lovense_dongle: false,
+            xinput: false,
+        };
+
+        let mut enabled = Vec::new();
+        if self.btleplug {
+            enabled.push((
+                "btleplug",
+                CommManagers {
+                    btleplug: true,
+                    ..none
+                },
+            ));
+        }
+        if self.lovense_dongle {
+            enabled.push((
+                "lovense_dongle",
+                CommManagers {
+                    lovense_dongle: true,
+                    ..none
+                },
+            ));
+        }
+        if self.xinput {
+            enabled.push((
+                "xinput",
+                CommManagers {
+                    xinput: true,
+                    ..none
+                },
+            ));
+        }
+        enabled
+    }
+
+    /// These managers with the ones in `other` turned off
+    fn without(self, other: CommManagers) -> Self {
+        Self {
+            btleplug: self.btleplug && !other.btleplug,
+            lovense_dongle: self.lovense_dongle && !other.lovense_dongle,
+            xinput: self.xinput && !other.xinput,
+        }
+    }
+}
+
+/// The managers the in-process server starts, managers that failed to start are turned off
+/// so reconnecting doesn't run into them again
+static COMM_MANAGERS: Lazy<Mutex<CommManagers>> = Lazy::new(|| Mutex::new(CommManagers::default()));
+
+/// Websocket address of Intiface Central, only set when connecting to it instead of an in-process server
+static WEBSOCKET_URL: OnceCell<String> = OnceCell::new();
+
+/// Choose the server to connect to, must be called before the first connection
+pub fn configure(kind: ConnectionKind, websocket_url: &str, comm_managers: CommManagers) {
+    if let Ok(mut managers) = COMM_MANAGERS.lock() {
+        *managers = comm_managers;
+    }
+
+    if kind == ConnectionKind::Intiface {
+        log::info!("connecting to Intiface Central at {websocket_url}");
+        let _ = WEBSOCKET_URL.set(websocket_url.to_string());
+    }
+}
+
+/// Build an in-process buttplug server with the configured device managers. When that fails each
+/// manager is tried on its own, and the server is built without the ones that can't start
+pub fn build_connector() -> Result<ButtplugInProcessClientConnector, ButtplugServerError> {
+    let comm_managers = COMM_MANAGERS
+        .lock()
+        .map(|managers| *managers)
+        .unwrap_or_default();
+
+    let server = match build_server(comm_managers) {
+        Ok(server) => server,
+        Err(e) => {
+            log::error!("error building server with all device managers: {e}");
+
+            let mut working = comm_managers;
+            for (name, only) in comm_managers.enabled() {
+                if let Err(e) = build_server(only) {
+                    show_error(&format!(
+                        "device manager {name} failed to start and is turned off: {e}\n\
+                         set {name} = false under [comm_managers] in the config to hide this"
+                    ));
+                    working = working.without(only);
+                }
+            }
+            if let Ok(mut managers) = COMM_MANAGERS.lock() {
+                *managers = working;
+            }
+
+            build_server(working).map_err(|e| {
+                show_error(&format!("no device support, error building server: {e}"));
+                e
+            })?
+        }
+    };
+
+    log::trace!("server built");
+
+    Ok(ButtplugInProcessClientConnectorBuilder::default()
+        .server(server)
+        .finish())
+}
+
+fn build_server(comm_managers: CommManagers) -> Result<ButtplugServer, ButtplugServerError> {
+    let mut builder = ButtplugServerBuilder::default();
+
+    if comm_managers.btleplug {
+        builder.comm_manager(BtlePlugCommunicationManagerBuilder::default());
+    }
+    if comm_managers.lovense_dongle {
+        builder.comm_manager(LovenseHIDDongleCommunicationManagerBuilder::default());
+    }
+    if comm_managers.xinput {
+        builder.comm_manager(XInputDeviceCommunicationManagerBuilder::default());
+    }
+
+    builder.finish()
+}
+
+/// Log an error and show it in a console window, which is opened for it if the console is turned off,
+/// the same way config errors are shown
+fn show_error(message: &str) {
+    log::error!("{message}");
+    unsafe {
+        windows::Win32::System::Console::AllocConsole();
+    }
+    println!("error: {message}");
+}
+
+/// Connect the client to Intiface Central or a freshly built server and start scanning for devices
+pub async fn connect(client: &ButtplugClient) -> bool {
+    let result = match WEBSOCKET_URL.get() {
+        Some(url) => {
+            let connector = ButtplugRemoteClientConnector::<
+                ButtplugWebsocketClientTransport,
+                ButtplugClientJSONSerializer,
+            >::new(
+                ButtplugWebsocketClientTransport::new_insecure_connector(url)
+            );
+            client.connect(connector).await
+        }
+        None => {
+            let connector = match build_connector() {
+                Ok(connector) => connector,
+                Err(e) => {
+                    log::error!("error building server: {e}");
+                    return false;
+                }
+            };
+            client.connect(connector).await
+        }
+    };
+
+    if let Err(e) = result {
+        log::error!("error connecting: {e}");
+        return false;
+    }
+
+    if let Err(e) = client.start_scanning().await {
+        log::error!("error scanning for devices: {e}")
+    }
+
+    true
+}
+
+/// Try to reconnect a disconnected client, giving up after `max_attempts` (0 tries forever)
+pub async fn reconnect(client: Arc<ButtplugClient>, max_attempts: u32) {
+    if RECONNECTING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let mut attempt = 0;
+    let mut delay = RECONNECT_DELAY;
+    loop {
+        attempt += 1;
+        log::info!("reconnecting to buttplug server, attempt {attempt}");
+
+        if connect(&client).await {
+            log::info!("reconnected to buttplug server");
+            break;
+        }
+
+        if max_attempts != 0 && attempt >= max_attempts {
+            log::error!(
+                "giving up reconnecting after {attempt} attempts, use the `rescan` console command to try again"
+            );
+            break;
+        }
+
+        log::info!("retrying in {}s", delay.as_secs());
+        sleep(delay).await;
+        delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+    }
+
+    RECONNECTING.store(false, Ordering::SeqCst);
+}
+
+/// Scan for devices again, reconnecting first if the client lost its connection
+pub async fn rescan(client: Arc<ButtplugClient>, max_attempts: u32) {
+    if client.connected() {
+        log::info!("rescanning for devices");
+        if let Err(e) = client.start_scanning().await {
+            log::error!("error scanning for devices: {e}")
+        }
+    } else {
+        reconnect(client, max_attempts).await;
+    }
+}
+
+/// Start scanning again while fewer than `expected` devices are connected, so devices turned on
+/// or coming back mid session are found. Scanning while a scan is already running is harmless
+pub async fn scan_for_missing_devices(
+    client: Arc<ButtplugClient>,
+    connected: usize,
+    expected: usize,
+) {
+    if connected >= expected
+        || !client.connected()
+        || is_idle()
+        || RECONNECTING.load(Ordering::SeqCst)
+    {
+        return;
+    }
+
+    log::debug!("{connected} of {expected} expected devices connected, scanning");
+    if let Err(e) = client.start_scanning().await {
+        log::debug!("error scanning for devices: {e}");
+    }
+}
+
+/// Whether the client was disconnected on purpose because the game has been idle
+pub fn is_idle() -> bool {
+    IDLE.load(Ordering::SeqCst)
+}
+
+/// Stop all devices, stop scanning and disconnect to save radio and battery while the game is idle
+pub fn idle_disconnect(client: Arc<ButtplugClient>) {
+    if IDLE.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = client.stop_all_devices().await {
+            log::error!("error stopping devices: {e}");
+        }
+        if let Err(e) = client.stop_scanning().await {
+            log::debug!("error stopping scanning: {e}");
+        }
+        if let Err(e) = client.disconnect().await {
+            log::error!("error disconnecting: {e}");
+        }
+    });
+}
+
+/// Reconnect after an idle disconnect
+pub fn wake(client: Arc<ButtplugClient>, max_attempts: u32) {
+    if !IDLE.swap(false, Ordering::SeqCst) {
+        return;
+    }
+
+    tokio::spawn(reconnect(client, max_attempts));
+}
@@ -0,0 +1,118 @@
+use std::io::BufRead;
+
+use crate::{
+    dll_code::{Event, CHANNEL_TX},
+    enabled,
+};
+
+const HELP: &str = "commands:
+    hit <intensity> <duration_ms>    vibrate as if a hit with the given intensity (0.0-1.0) landed
+    pin <device name> <intensity>    hold a device at a fixed intensity, ignoring the game
+    release <device name>            return a pinned device to normal
+    enable                           turn feedback back on
+    disable                          stop all devices and ignore the game until enabled again
+    rescan                           scan for devices again, reconnecting if the connection was lost
+    resync                           rebuild the device list from the devices that are actually connected
+    help                             show this message";
+
+/// Open a console window and handle commands typed into it on a separate thread
+pub fn spawn_console() {
+    unsafe {
+        windows::Win32::System::Console::AllocConsole();
+    }
+
+    println!("acpr_buttplug console, type `help` for a list of commands");
+
+    std::thread::spawn(|| {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+
+            match parse_command(&line) {
+                Ok(Some(event)) => send_event(event),
+                Ok(None) => {}
+                Err(e) => println!("{e}"),
+            }
+        }
+
+        log::debug!("console input closed");
+    });
+}
+
+/// Parse a line of console input, returning the event it should send if any
+fn parse_command(line: &str) -> Result<Option<Event>, String> {
+    let mut args = line.split_whitespace();
+
+    match args.next() {
+        None => Ok(None),
+        Some("help") => {
+            println!("{HELP}");
+            Ok(None)
+        }
+        Some("rescan") => Ok(Some(Event::Rescan)),
+        Some("resync") => Ok(Some(Event::ResyncDevices)),
+        Some("enable") => {
+            enabled::set_enabled(true);
+            Ok(None)
+        }
+        Some("disable") => {
+            enabled::set_enabled(false);
+            Ok(None)
+        }
+        Some("pin") => {
+            // device names can contain spaces, so the intensity is always the last argument
+            let mut rest: Vec<&str> = args.collect();
+            let intensity = rest
+                .pop()
+                .ok_or("usage: pin <device name> <intensity>")?
+                .parse::<f64>()
+                .map_err(|e| format!("invalid intensity: {e}"))?;
+
+            if rest.is_empty() {
+                return Err("usage: pin <device name> <intensity>".into());
+            }
+
+            Ok(Some(Event::PinDevice {
+                name: rest.join(" "),
+                intensity: intensity.clamp(0.0, 1.0),
+            }))
+        }
+        Some("release") => {
+            let name = args.collect::<Vec<_>>().join(" ");
+            if name.is_empty() {
+                return Err("usage: release <device name>".into());
+            }
+
+            Ok(Some(Event::ReleaseDevice { name }))
+        }
+        Some("hit") => {
+            let intensity = args
+                .next()
+                .ok_or("usage: hit <intensity> <duration_ms>")?
+                .parse::<f64>()
+                .map_err(|e| format!("invalid intensity: {e}"))?;
+            let duration_ms = args
+                .next()
+                .ok_or("usage: hit <intensity> <duration_ms>")?
+                .parse::<u64>()
+                .map_err(|e| format!("invalid duration: {e}"))?;
+
+            Ok(Some(Event::SimulatedHit {
+                intensity: intensity.clamp(0.0, 1.0),
+                duration_ms,
+            }))
+        }
+        Some(command) => Err(format!(
+            "unknown command `{command}`, type `help` for a list"
+        )),
+    }
+}
+
+fn send_event(event: Event) {
+    if let Some(channel) = CHANNEL_TX.get() {
+        if channel.send(event).is_err() {
+            log::error!("console command dropped, event loop isn't running");
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use serde::Deserialize;
+
+/// How a normalized hit strength from 0.0 to 1.0 maps to vibration intensity
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Curve {
+    #[default]
+    Linear,
+    /// Raises the strength to the power of `gamma`, below 1.0 boosts light hits and above 1.0 weakens them
+    Exponential { gamma: f64 },
+    /// Straight lines between `[strength, intensity]` points, strengths outside the points use the nearest one
+    Points { points: Vec<[f64; 2]> },
+}
+
+impl Curve {
+    pub fn apply(&self, strength: f64) -> f64 {
+        let strength = strength.clamp(0.0, 1.0);
+
+        let intensity = match self {
+            Curve::Linear => strength,
+            Curve::Exponential { gamma } => strength.powf(*gamma),
+            Curve::Points { points } => interpolate(points, strength),
+        };
+
+        intensity.clamp(0.0, 1.0)
+    }
+}
+
+fn interpolate(points: &[[f64; 2]], strength: f64) -> f64 {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a[0].total_cmp(&b[0]));
+
+    let (Some(first), Some(last)) = (points.first(), points.last()) else {
+        return strength;
+    };
+
+    if strength <= first[0] {
+        return first[1];
+    }
+
+    for pair in points.windows(2) {
+        let ([x0, y0], [x1, y1]) = (pair[0], pair[1]);
+        if strength <= x1 {
+            if x1 - x0 <= f64::EPSILON {
+                return y1;
+            }
+            return y0 + (strength - x0) / (x1 - x0) * (y1 - y0);
+        }
+    }
+
+    last[1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn linear_is_identity() {
+        for strength in [0.0, 0.25, 0.5, 0.75, 1.0] {
+            assert_eq!(Curve::Linear.apply(strength), strength);
+        }
+    }
+
+    #[test]
+    fn endpoints_map_to_0_and_1() {
+        let curves = [
+            Curve::Linear,
+            Curve::Exponential { gamma: 0.5 },
+            Curve::Exponential { gamma: 2.0 },
+            Curve::Points {
+                points: vec![[0.0, 0.0], [0.3, 0.6], [1.0, 1.0]],
+            },
+        ];
+
+        for curve in curves {
+            assert_eq!(curve.apply(0.0), 0.0, "{curve:?}");
+            assert_eq!(curve.apply(1.0), 1.0, "{curve:?}");
+        }
+    }
+
+    #[test]
+    fn gamma_below_1_raises_low_inputs() {
+        let curve = Curve::Exponential { gamma: 0.5 };
+
+        for strength in [0.05, 0.1, 0.25] {
+            assert!(curve.apply(strength) > strength);
+        }
+    }
+}
@@ -0,0 +1,146 @@
+use std::{collections::HashMap, sync::Arc};
+
+use buttplug::client::ButtplugClientDevice;
+use once_cell::sync::OnceCell;
+use tokio::sync::{
+    mpsc::{self, UnboundedReceiver, UnboundedSender},
+    watch,
+};
+
+use crate::dll_code::{stop_vibration, vibrate_device};
+
+/// A command for a device, sent from the polling loop to the dispatcher
+pub enum DeviceCommand {
+    Vibrate(Arc<ButtplugClientDevice>, f64),
+    Stop(Arc<ButtplugClientDevice>),
+}
+
+impl DeviceCommand {
+    fn device(&self) -> &Arc<ButtplugClientDevice> {
+        match self {
+            DeviceCommand::Vibrate(dev, _) | DeviceCommand::Stop(dev) => dev,
+        }
+    }
+
+    fn target(&self) -> Target {
+        match self {
+            DeviceCommand::Vibrate(_, strength) => Target::Vibrate(*strength),
+            DeviceCommand::Stop(_) => Target::Stop,
+        }
+    }
+}
+
+/// What a device should be doing, a device's mailbox only holds the newest one
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Vibrate(f64),
+    Stop,
+}
+
+/// What the dispatcher receives, commands for devices and devices to forget
+enum Message {
+    Command(DeviceCommand),
+    /// A device was removed, by its index
+    Forget(u32),
+}
+
+/// The mailbox of a device's task, along with the device the task was started for
+struct Mailbox {
+    dev: Arc<ButtplugClientDevice>,
+    target: watch::Sender<Target>,
+}
+
+static COMMAND_TX: OnceCell<UnboundedSender<Message>> = OnceCell::new();
+
+/// Start sending device commands from a dedicated thread with its own runtime,
+/// so slow devices can't delay polling the game state
+pub fn spawn_dispatcher() {
+    let (tx, rx) = mpsc::unbounded_channel();
+    if COMMAND_TX.set(tx).is_err() {
+        return;
+    }
+
+    std::thread::spawn(|| {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(dispatch_commands(rx))
+    });
+}
+
+/// Queue a command for the dispatcher without waiting for the device
+pub fn send(command: DeviceCommand) {
+    send_message(Message::Command(command));
+}
+
+/// End the task of a removed device, it finishes the command in flight and drops the rest
+pub fn forget(dev: &ButtplugClientDevice) {
+    send_message(Message::Forget(dev.index()));
+}
+
+fn send_message(message: Message) {
+    let Some(tx) = COMMAND_TX.get() else {
+        return;
+    };
+
+    if tx.send(message).is_err() {
+        log::error!("device dispatcher stopped, dropping command");
+    }
+}
+
+/// Hand every command to the task of its device. Each device has a single slot mailbox, so while
+/// a command is in flight newer ones replace the waiting one instead of queueing up behind it,
+/// and a device never has more than one command in flight
+async fn dispatch_commands(mut messages: UnboundedReceiver<Message>) {
+    let mut mailboxes: HashMap<u32, Mailbox> = HashMap::new();
+
+    while let Some(message) = messages.recv().await {
+        let command = match message {
+            Message::Command(command) => command,
+            // dropping the mailbox ends the task
+            Message::Forget(index) => {
+                mailboxes.remove(&index);
+                continue;
+            }
+        };
+
+        let dev = command.device();
+        let target = command.target();
+
+        match mailboxes.get(&dev.index()) {
+            // a device that reconnected under the same index gets a new task,
+            // dropping the old mailbox ends the task of the old device
+            Some(mailbox) if Arc::ptr_eq(&mailbox.dev, dev) => {
+                mailbox.target.send_replace(target);
+            }
+            _ => {
+                let (sender, receiver) = watch::channel(target);
+                tokio::spawn(drive_device(dev.clone(), receiver));
+                mailboxes.insert(
+                    dev.index(),
+                    Mailbox {
+                        dev: dev.clone(),
+                        target: sender,
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Send the newest target of one device each time the previous command has completed
+async fn drive_device(dev: Arc<ButtplugClientDevice>, mut mailbox: watch::Receiver<Target>) {
+    loop {
+        let target = *mailbox.borrow_and_update();
+        match target {
+            Target::Vibrate(strength) => vibrate_device(dev.clone(), strength).await,
+            Target::Stop => stop_vibration(dev.clone()).await,
+        }
+
+        if mailbox.changed().await.is_err() {
+            log::debug!("stopped sending commands to {}", dev.name());
+            return;
+        }
+    }
+}
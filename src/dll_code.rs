@@ -1,53 +1,803 @@
 use std::{
-    io::{Read, Write},
+    collections::{HashMap, VecDeque},
     path::PathBuf,
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use buttplug::{
-    client::{ButtplugClient, ButtplugClientDevice, ButtplugClientEvent, VibrateCommand},
-    core::connector::ButtplugInProcessClientConnectorBuilder,
-    server::{
-        device::hardware::communication::{
-            btleplug::BtlePlugCommunicationManagerBuilder,
-            lovense_dongle::LovenseHIDDongleCommunicationManagerBuilder,
-            xinput::XInputDeviceCommunicationManagerBuilder,
-        },
-        ButtplugServerBuilder,
-    },
+use buttplug::client::{
+    ButtplugClient, ButtplugClientDevice, ButtplugClientEvent, LinearCommand, RotateCommand,
+    VibrateCommand,
 };
-use futures::StreamExt;
+use futures::{Future, StreamExt};
 use log::LevelFilter;
 use once_cell::sync::{Lazy, OnceCell};
-use serde::Deserialize;
-use std::sync::mpsc::Sender;
+use serde::{
+    de::{value::StrDeserializer, IntoDeserializer},
+    Deserialize,
+};
+use tokio::sync::mpsc::UnboundedSender;
 use tokio::{sync::Mutex, time::sleep};
 
 use crate::{
-    global::{PLAYER_1_STATE, PLAYER_2_STATE},
+    battery,
+    connection::{self, CommManagers, ConnectionKind},
+    console,
+    curve::Curve,
+    dispatch::{self, DeviceCommand},
+    enabled, eventlog,
+    helpers::{self, read_guarded, Offset},
     hooks,
+    hotkey::Hotkey,
+    known_devices,
+    light::{Light, LightProtocol},
+    offsets,
+    osc::{self, OscOutput, OscOutputConfig},
+    overlay::{Overlay, OverlayState},
+    pattern::{combine_layers, decaying_pulses, start_pattern, PatternPlayer, PatternStep},
+    simulate::Simulation,
+    stats,
+    telemetry::{Telemetry, TelemetryMode},
 };
 
 #[derive(Debug, Deserialize)]
+#[serde(default)]
 pub struct Config {
     vibration_strength: f64,
     log_level: LevelFilter,
+    device_mode: DeviceMode,
+    wave_stagger_ms: u64,
+    wave_order: Vec<String>,
+    health_differential: HealthDifferential,
+    health_differential_strength: f64,
+    max_health: i16,
+    desperation_strength: f64,
+    desperation_curve: Curve,
+    telemetry: TelemetryMode,
+    telemetry_path: PathBuf,
+    devices: HashMap<String, DeviceConfig>,
+    device_strength: HashMap<String, f64>,
+    intensity_source: IntensitySource,
+    max_hitstun: f64,
+    hit_rate_window_ms: u64,
+    min_hit_rate: f64,
+    max_hit_rate: f64,
+    ko_pattern: Vec<PatternStep>,
+    double_ko_pattern: Vec<PatternStep>,
+    blocked_super_pattern: Vec<PatternStep>,
+    big_hit_hitstop: u8,
+    big_hit_pattern: Vec<PatternStep>,
+    player: u8,
+    throw_pattern: Vec<PatternStep>,
+    thrown_pattern: Vec<PatternStep>,
+    instant_block_pattern: Vec<PatternStep>,
+    faultless_defense_pattern: Vec<PatternStep>,
+    super_flash_pattern: Vec<PatternStep>,
+    master_intensity_osc_port: Option<u16>,
+    master_intensity_osc_address: String,
+    hit_confirmation_window_ms: u64,
+    console: bool,
+    light_port: Option<String>,
+    light_baud_rate: u32,
+    light_protocol: LightProtocol,
+    light_channel: u16,
+    critical_battery_level: f64,
+    critical_battery_max_intensity: f64,
+    combo_mode: ComboMode,
+    combo_decay: f64,
+    combo_decay_floor: f64,
+    combo_escalation: f64,
+    combo_escalation_cap: f64,
+    meter_mode: MeterMode,
+    max_tension: f64,
+    meter_intensity: f64,
+    meter_change_threshold: i32,
+    meter_pulse_ms: u64,
+    max_reconnect_attempts: u32,
+    test_on_connect: bool,
+    idle_disconnect_secs: u64,
+    verbose_state: bool,
+    streak_bonus: f64,
+    streak_bonus_cap: f64,
+    hit_dedup_window_ms: u64,
+    priorities: Priorities,
+    ducking: f64,
+    distance_weight: f64,
+    max_distance: f64,
+    persist_enabled_state: bool,
+    log_levels: HashMap<String, LevelFilter>,
+    enable_hooks: bool,
+    event_log: bool,
+    pause_when_unfocused: bool,
+    focus_loss: FocusLoss,
+    idle_intensity: f64,
+    session_vibration_budget_secs: u64,
+    match_point_boost: f64,
+    match_point_boost_ms: u64,
+    rounds_to_win: u8,
+    connection: ConnectionSetting,
+    websocket_url: String,
+    poll_interval_ms: u64,
+    curve: Curve,
+    max_hitstop: f64,
+    block_multiplier: f64,
+    rescan_interval_secs: u64,
+    expected_devices: usize,
+    hit_pulse_strength: f64,
+    hit_pulse_ms: u64,
+    min_intensity: f64,
+    player_mode: PlayerMode,
+    emergency_stop_key: Option<String>,
+    low_battery_level: f64,
+    comm_managers: CommManagers,
+    game_load_timeout_secs: u64,
+    deadman_ticks: u32,
+    max_continuous_ms: u64,
+    ramp_rate: f64,
+    simulate: bool,
+    /// Keyed by name as toml can't deserialize enum keys, `validate` turns it into `hit_type_settings`
+    hit_types: HashMap<String, HitTypeConfig>,
+    #[serde(skip)]
+    hit_type_settings: HashMap<HitType, HitTypeConfig>,
+    osc: Option<OscOutputConfig>,
+    overlay_port: Option<u16>,
+    external_hit_ms: u64,
+    command_retries: u32,
+    command_retry_delay_ms: u64,
+    command_timeout_ms: u64,
+    invert: bool,
+    client_name: String,
+    log_file: PathBuf,
+    ignore_unknown_devices: bool,
+    whiff_strength: f64,
+    whiff_ms: u64,
+    vibration: VibrationSection,
+    logging: LoggingSection,
+    toggle_key: Option<String>,
+    knockdown_strength: f64,
+    knockdown_ms: u64,
+    knockdown_pulse_ms: u64,
+    telemetry_max_size_mb: u64,
+    combine_mode: CombineMode,
+}
+
+// keep in sync with default_config.toml, fields missing from a config fall back to these
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vibration_strength: 0.5,
+            log_level: LevelFilter::Error,
+            device_mode: DeviceMode::All,
+            wave_stagger_ms: 30,
+            wave_order: Vec::new(),
+            health_differential: HealthDifferential::Off,
+            health_differential_strength: 0.5,
+            max_health: 420,
+            desperation_strength: 0.0,
+            desperation_curve: Curve::Linear,
+            telemetry: TelemetryMode::Off,
+            telemetry_path: PathBuf::from("acpr_buttplug_telemetry.csv"),
+            devices: HashMap::new(),
+            device_strength: HashMap::new(),
+            intensity_source: IntensitySource::Hitstop,
+            max_hitstun: 30.0,
+            hit_rate_window_ms: 1000,
+            min_hit_rate: 1.0,
+            max_hit_rate: 8.0,
+            ko_pattern: Vec::new(),
+            double_ko_pattern: Vec::new(),
+            blocked_super_pattern: Vec::new(),
+            big_hit_hitstop: 0,
+            big_hit_pattern: Vec::new(),
+            player: 0,
+            throw_pattern: Vec::new(),
+            thrown_pattern: Vec::new(),
+            instant_block_pattern: Vec::new(),
+            faultless_defense_pattern: Vec::new(),
+            // a hard pulse, a beat of nothing, then a sustained rumble through the freeze
+            super_flash_pattern: vec![
+                PatternStep {
+                    intensity: 1.0,
+                    duration_ms: 150,
+                },
+                PatternStep {
+                    intensity: 0.0,
+                    duration_ms: 100,
+                },
+                PatternStep {
+                    intensity: 0.7,
+                    duration_ms: 600,
+                },
+            ],
+            master_intensity_osc_port: None,
+            master_intensity_osc_address: "/acpr_buttplug/intensity".into(),
+            hit_confirmation_window_ms: 50,
+            console: false,
+            light_port: None,
+            light_baud_rate: 115200,
+            light_protocol: LightProtocol::Raw,
+            light_channel: 1,
+            critical_battery_level: 0.0,
+            critical_battery_max_intensity: 0.3,
+            combo_mode: ComboMode::Off,
+            combo_decay: 0.9,
+            combo_decay_floor: 0.3,
+            combo_escalation: 0.05,
+            combo_escalation_cap: 1.0,
+            meter_mode: MeterMode::Off,
+            max_tension: 10000.0,
+            meter_intensity: 0.3,
+            meter_change_threshold: 1000,
+            meter_pulse_ms: 150,
+            max_reconnect_attempts: 10,
+            test_on_connect: false,
+            idle_disconnect_secs: 0,
+            verbose_state: false,
+            streak_bonus: 0.0,
+            streak_bonus_cap: 0.5,
+            hit_dedup_window_ms: 20,
+            priorities: Priorities::default(),
+            ducking: 0.0,
+            distance_weight: 0.0,
+            max_distance: 100000.0,
+            persist_enabled_state: true,
+            log_levels: HashMap::new(),
+            enable_hooks: false,
+            event_log: false,
+            pause_when_unfocused: false,
+            focus_loss: FocusLoss::Stop,
+            idle_intensity: 0.0,
+            session_vibration_budget_secs: 0,
+            match_point_boost: 0.0,
+            match_point_boost_ms: 500,
+            rounds_to_win: 2,
+            connection: ConnectionSetting::Kind(ConnectionKind::InProcess),
+            websocket_url: "ws://127.0.0.1:12345".into(),
+            poll_interval_ms: 7,
+            curve: Curve::Linear,
+            // highest possible hitstop for a normal is 27f
+            // moving this to 28 allows edge cases to have a bigger response
+            max_hitstop: 28.0,
+            block_multiplier: 0.5,
+            rescan_interval_secs: 30,
+            expected_devices: 1,
+            hit_pulse_strength: 0.0,
+            hit_pulse_ms: 40,
+            min_intensity: 0.05,
+            player_mode: PlayerMode::Both,
+            emergency_stop_key: Some("F8".into()),
+            low_battery_level: 0.2,
+            comm_managers: CommManagers::default(),
+            game_load_timeout_secs: 300,
+            deadman_ticks: 30,
+            max_continuous_ms: 5000,
+            ramp_rate: 1.0,
+            simulate: false,
+            hit_types: HashMap::new(),
+            hit_type_settings: HashMap::new(),
+            osc: None,
+            overlay_port: None,
+            external_hit_ms: 150,
+            command_retries: 2,
+            command_retry_delay_ms: 50,
+            command_timeout_ms: 500,
+            invert: false,
+            client_name: "Buttplug Mod".into(),
+            log_file: "acprmod.log".into(),
+            ignore_unknown_devices: false,
+            whiff_strength: 0.0,
+            whiff_ms: 40,
+            vibration: VibrationSection::default(),
+            logging: LoggingSection::default(),
+            toggle_key: Some("F9".into()),
+            knockdown_strength: 0.0,
+            knockdown_ms: 1200,
+            knockdown_pulse_ms: 100,
+            telemetry_max_size_mb: 50,
+            combine_mode: CombineMode::Max,
+        }
+    }
+}
+
+impl Config {
+    /// Move values from the `[vibration]`, `[connection]` and `[logging]` sections over the
+    /// top level keys they replace, which still work for configs from before the sections existed
+    fn apply_sections(&mut self) {
+        fn set<T>(target: &mut T, value: Option<T>) {
+            if let Some(value) = value {
+                *target = value;
+            }
+        }
+
+        let vibration = std::mem::take(&mut self.vibration);
+        set(&mut self.vibration_strength, vibration.strength);
+        set(&mut self.min_intensity, vibration.min_intensity);
+        set(&mut self.idle_intensity, vibration.idle_intensity);
+        set(&mut self.max_hitstop, vibration.max_hitstop);
+        set(&mut self.curve, vibration.curve);
+        set(&mut self.invert, vibration.invert);
+        set(&mut self.block_multiplier, vibration.block_multiplier);
+        set(&mut self.ramp_rate, vibration.ramp_rate);
+
+        if let ConnectionSetting::Section(section) = &mut self.connection {
+            set(&mut self.websocket_url, section.websocket_url.take());
+            self.connection =
+                ConnectionSetting::Kind(section.kind.unwrap_or(ConnectionKind::InProcess));
+        }
+
+        let logging = std::mem::take(&mut self.logging);
+        set(&mut self.log_level, logging.level);
+        set(&mut self.log_file, logging.file);
+        set(&mut self.verbose_state, logging.verbose_state);
+        set(&mut self.event_log, logging.event_log);
+    }
+
+    /// Which buttplug server to use, from either the `[connection]` section or the older top level key
+    fn connection_kind(&self) -> ConnectionKind {
+        match &self.connection {
+            ConnectionSetting::Kind(kind) => *kind,
+            ConnectionSetting::Section(section) => {
+                section.kind.unwrap_or(ConnectionKind::InProcess)
+            }
+        }
+    }
+
+    /// Fix up values that would break the mod, returning a warning describing what was changed
+    fn validate(&mut self) -> Option<String> {
+        let mut warnings = Vec::new();
+
+        if self.max_hitstop <= 0.0 {
+            warnings.push(format!(
+                "max_hitstop of {} must be above 0, using 28",
+                self.max_hitstop
+            ));
+            self.max_hitstop = 28.0;
+        }
+
+        if !(0.0..=1.0).contains(&self.block_multiplier) {
+            let clamped = self.block_multiplier.clamp(0.0, 1.0);
+            warnings.push(format!(
+                "block_multiplier of {} is outside 0.0 to 1.0, using {clamped}",
+                self.block_multiplier
+            ));
+            self.block_multiplier = clamped;
+        }
+
+        if self.ramp_rate <= 0.0 {
+            warnings.push(format!(
+                "ramp_rate of {} would never change the intensity, using 1.0",
+                self.ramp_rate
+            ));
+            self.ramp_rate = 1.0;
+        }
+
+        if self.poll_interval_ms < 1 {
+            warnings.push(format!(
+                "poll_interval_ms of {} is too low, using 1",
+                self.poll_interval_ms
+            ));
+            self.poll_interval_ms = 1;
+        }
+
+        for (name, settings) in &self.hit_types {
+            match HitType::from_name(name) {
+                Some(hit_type) => {
+                    self.hit_type_settings.insert(hit_type, settings.clone());
+                }
+                None => warnings.push(format!(
+                    "unknown hit type hit_types.{name}, use normal or super, ignoring it"
+                )),
+            }
+        }
+
+        (!warnings.is_empty()).then(|| warnings.join(", "))
+    }
+
+    /// Check for values that have no sensible fix, describing every problem on its own line
+    fn check(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+
+        let mut between_0_and_1 = |name: &str, value: f64| {
+            if !(0.0..=1.0).contains(&value) {
+                problems.push(format!("{name} is {value}, it must be from 0.0 to 1.0"));
+            }
+        };
+        between_0_and_1("vibration_strength", self.vibration_strength);
+        between_0_and_1("min_intensity", self.min_intensity);
+        between_0_and_1("idle_intensity", self.idle_intensity);
+        between_0_and_1("hit_pulse_strength", self.hit_pulse_strength);
+        between_0_and_1("whiff_strength", self.whiff_strength);
+        between_0_and_1("knockdown_strength", self.knockdown_strength);
+        between_0_and_1("meter_intensity", self.meter_intensity);
+        between_0_and_1("ducking", self.ducking);
+        between_0_and_1("combo_decay", self.combo_decay);
+        between_0_and_1("combo_decay_floor", self.combo_decay_floor);
+        between_0_and_1("low_battery_level", self.low_battery_level);
+        between_0_and_1("critical_battery_level", self.critical_battery_level);
+        between_0_and_1(
+            "critical_battery_max_intensity",
+            self.critical_battery_max_intensity,
+        );
+        for (name, device) in &self.devices {
+            if let Some(strength) = device.strength {
+                between_0_and_1(&format!("devices.\"{name}\".strength"), strength);
+            }
+        }
+        for (name, strength) in &self.device_strength {
+            between_0_and_1(&format!("device_strength.\"{name}\""), *strength);
+        }
+
+        for (name, device) in &self.devices {
+            if let Some(player) = device.player.filter(|player| !(1..=2).contains(player)) {
+                problems.push(format!(
+                    "devices.\"{name}\".player is {player}, it must be 1 or 2"
+                ));
+            }
+        }
+
+        let mut not_negative = |name: &str, value: f64| {
+            if value < 0.0 {
+                problems.push(format!("{name} is {value}, it can't be negative"));
+            }
+        };
+        not_negative(
+            "health_differential_strength",
+            self.health_differential_strength,
+        );
+        not_negative("streak_bonus", self.streak_bonus);
+        not_negative("streak_bonus_cap", self.streak_bonus_cap);
+        not_negative("combo_escalation", self.combo_escalation);
+        not_negative("combo_escalation_cap", self.combo_escalation_cap);
+        not_negative("distance_weight", self.distance_weight);
+        not_negative("match_point_boost", self.match_point_boost);
+        not_negative("desperation_strength", self.desperation_strength);
+        for (hit_type, settings) in &self.hit_types {
+            not_negative(&format!("hit_types.{hit_type}.scale"), settings.scale);
+        }
+
+        let mut above_0 = |name: &str, value: f64| {
+            if value <= 0.0 {
+                problems.push(format!("{name} is {value}, it must be above 0"));
+            }
+        };
+        above_0("max_hitstun", self.max_hitstun);
+        above_0("max_tension", self.max_tension);
+        above_0("max_distance", self.max_distance);
+        // a gamma of 0 or below maps every hit, however light, to full intensity
+        for (name, curve) in [
+            ("curve", &self.curve),
+            ("desperation_curve", &self.desperation_curve),
+        ] {
+            if let Curve::Exponential { gamma } = curve {
+                above_0(&format!("{name}.gamma"), *gamma);
+            }
+        }
+
+        if self.rounds_to_win == 0 {
+            problems.push("rounds_to_win is 0, it must be at least 1".to_string());
+        }
+
+        if self.min_hit_rate >= self.max_hit_rate {
+            problems.push(format!(
+                "min_hit_rate of {} must be below max_hit_rate of {}",
+                self.min_hit_rate, self.max_hit_rate
+            ));
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("invalid config:\n  {}", problems.join("\n  ")))
+        }
+    }
+
+    /// Settings for a device by its name, devices without an entry use the defaults.
+    /// A strength under `[devices]` takes precedence over one in `[device_strength]`
+    fn device(&self, name: &str) -> DeviceConfig {
+        let mut device = self.devices.get(name).cloned().unwrap_or_default();
+        if device.strength.is_none() {
+            device.strength = self.device_strength.get(name).copied();
+        }
+        device
+    }
+}
+
+/// The `[vibration]` section, anything left out keeps the value of the older top level key
+#[derive(Debug, Default, Deserialize)]
+pub struct VibrationSection {
+    strength: Option<f64>,
+    min_intensity: Option<f64>,
+    idle_intensity: Option<f64>,
+    max_hitstop: Option<f64>,
+    curve: Option<Curve>,
+    invert: Option<bool>,
+    block_multiplier: Option<f64>,
+    ramp_rate: Option<f64>,
+}
+
+/// The `[logging]` section, anything left out keeps the value of the older top level key
+#[derive(Debug, Default, Deserialize)]
+pub struct LoggingSection {
+    level: Option<LevelFilter>,
+    file: Option<PathBuf>,
+    verbose_state: Option<bool>,
+    event_log: Option<bool>,
+}
+
+/// The `[connection]` section, anything left out keeps the value of the older top level key
+#[derive(Debug, Default, Deserialize)]
+pub struct ConnectionSection {
+    kind: Option<ConnectionKind>,
+    websocket_url: Option<String>,
+}
+
+/// `connection` is either the `[connection]` section or the older top level key naming the server
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum ConnectionSetting {
+    Kind(ConnectionKind),
+    Section(ConnectionSection),
+}
+
+/// Which game value drives the vibration intensity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntensitySource {
+    /// Hitstop of the current hit, short and sharp
+    Hitstop,
+    /// Remaining hitstun of the defender, a longer sustained feel on heavy hits
+    Hitstun,
+    /// How many hits landed recently, pressure builds up over fast strings of hits
+    HitRate,
+}
+
+/// Whose hitstop and hitstun drive the vibration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerMode {
+    /// Both players, merged according to the `CombineMode`
+    Both,
+    Player1,
+    Player2,
+}
+
+/// How both players hitstop is merged into one value with `player_mode` "both"
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CombineMode {
+    /// Whichever player has more
+    Max,
+    /// Both added together, still clamped to full intensity by the curve
+    Sum,
+    /// The mean of both
+    Average,
+}
+
+/// What happens to devices while the game doesn't have focus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusLoss {
+    /// Stop all devices
+    Stop,
+    /// Keep devices going at `idle_intensity`, so a quick alt-tab doesn't interrupt everything,
+    /// with the default `idle_intensity` of 0.0 this is the same as stopping
+    Idle,
+}
+
+/// How the tension gauge produces feedback outside of hits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeterMode {
+    Off,
+    /// Gaining or spending a chunk of tension plays a short pulse
+    Change,
+    /// A constant vibration that grows with the fullest tension gauge
+    Level,
+}
+
+/// How the length of a combo affects the intensity of each hit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComboMode {
+    Off,
+    /// Each hit of a combo is weaker than the last, like the games damage scaling
+    Decay,
+    /// Each hit of a combo is stronger than the last, so long combos build up
+    Escalate,
+}
+
+/// Priority of each kind of feedback when several overlap, the highest one plays at
+/// full strength while the others are ducked
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Priorities {
+    hitstop: u8,
+    simulated_hit: u8,
+    ko: u8,
+    double_ko: u8,
+    blocked_super: u8,
+    meter: u8,
+    big_hit: u8,
+    throw: u8,
+    defense: u8,
+    hit_pulse: u8,
+    hit_type: u8,
+    super_flash: u8,
+    external_hit: u8,
+    whiff: u8,
+    knockdown: u8,
+}
+
+impl Default for Priorities {
+    fn default() -> Self {
+        Self {
+            hitstop: 1,
+            simulated_hit: 3,
+            ko: 2,
+            double_ko: 3,
+            blocked_super: 2,
+            meter: 1,
+            big_hit: 2,
+            throw: 2,
+            defense: 1,
+            hit_pulse: 1,
+            hit_type: 2,
+            super_flash: 3,
+            external_hit: 2,
+            whiff: 0,
+            knockdown: 2,
+        }
+    }
+}
+
+/// What kind of move a hit came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HitType {
+    Normal,
+    /// A hit landed while either player has a super active
+    Super,
+}
+
+impl HitType {
+    /// Hit type from the name it has in the config
+    fn from_name(name: &str) -> Option<Self> {
+        let name: StrDeserializer<serde::de::value::Error> = name.into_deserializer();
+        Self::deserialize(name).ok()
+    }
+}
+
+/// How hits of one type feel
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HitTypeConfig {
+    /// Multiplies the intensity for as long as the hit lasts
+    scale: f64,
+    /// Played over the normal feedback when the hit lands
+    pattern: Vec<PatternStep>,
+}
+
+impl Default for HitTypeConfig {
+    fn default() -> Self {
+        Self {
+            scale: 1.0,
+            pattern: Vec::new(),
+        }
+    }
+}
+
+/// Per device settings, keyed by the name the device reports
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DeviceConfig {
+    enabled: bool,
+    /// Replaces the global vibration_strength for this device
+    strength: Option<f64>,
+    /// Scale for each vibration motor on devices with more than one, motors without an entry use 1.0
+    actuator_scales: Vec<f64>,
+    /// Only vibrate for hits this player (1 or 2) takes, devices without one feel every hit
+    player: Option<u8>,
+}
+
+impl Default for DeviceConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            strength: None,
+            actuator_scales: Vec::new(),
+            player: None,
+        }
+    }
+}
+
+/// How hits are distributed between connected devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceMode {
+    /// Every device vibrates on every hit
+    All,
+    /// Each successive hit is sent to the next device in turn
+    RoundRobin,
+    /// Every device vibrates on every hit, each starting a little after the one before it
+    Wave,
+}
+
+/// How the health difference between attacker and defender affects intensity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthDifferential {
+    Off,
+    /// Hits from the player that is ahead on health feel stronger
+    Momentum,
+    /// Hits from the player that is behind on health feel stronger
+    Comeback,
 }
 
 pub enum Event {
-    Hit,
+    Hit(HitType),
+    /// One of the players was knocked out
+    Ko,
+    /// Both players were knocked out on the same frame
+    DoubleKo,
+    /// A player started blocking the other players super
+    BlockedSuper,
+    /// A player blocked with an Instant Block
+    InstantBlock,
+    /// A player started using Faultless Defense
+    FaultlessDefense,
+    /// A player started a super and the screen froze for the super flash
+    SuperFlash,
+    /// An attack ended without hitting or being blocked
+    Whiff,
+    /// A player was knocked down
+    Knockdown,
+    /// A knocked down player got back up
+    Wakeup,
+    /// A player was thrown, `thrower` is the index of the player that threw them
+    Throw {
+        thrower: usize,
+    },
+    /// A players tension gauge changed by at least the configured threshold
+    MeterChanged,
+    /// Scan for devices again, reconnecting first if the reconnect attempts ran out
+    Rescan,
+    /// Rebuild the device list from the devices the client actually has
+    ResyncDevices,
+    /// A hit requested from the console for calibrating settings
+    SimulatedHit {
+        intensity: f64,
+        duration_ms: u64,
+    },
+    /// A hit pushed by another mod through `acpr_buttplug_push_hit`
+    ExternalHit {
+        intensity: f64,
+    },
+    /// Hold the device named `name` at a fixed intensity from the console, ignoring the game
+    PinDevice {
+        name: String,
+        intensity: f64,
+    },
+    /// Return a pinned device to following the game
+    ReleaseDevice {
+        name: String,
+    },
 }
 
 pub static CONFIG: OnceCell<Config> = OnceCell::new();
-pub static CHANNEL_TX: Lazy<Mutex<Option<Sender<Event>>>> = Lazy::new(|| Mutex::new(None));
-pub static HIT_CHANNEL_TX: Lazy<Mutex<Option<Sender<Event>>>> = Lazy::new(|| Mutex::new(None));
+/// Events from the console and other control inputs
+pub static CHANNEL_TX: OnceCell<UnboundedSender<Event>> = OnceCell::new();
+/// Events from the hooks, sending never blocks so the game thread isn't held up
+pub static HIT_CHANNEL_TX: OnceCell<UnboundedSender<Event>> = OnceCell::new();
 
 const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
 
 /// User code for initializing the DLL goes here
 pub fn initialize() {
-    let config_result = setup_config();
+    let mut config_warning = None;
+    let config_result = setup_config().map(|(config, warning)| {
+        config_warning = warning;
+        config
+    });
 
     if let Err(ref e) = config_result {
         unsafe {
@@ -56,28 +806,41 @@ pub fn initialize() {
         println!("error: {e}")
     }
 
-    let config = CONFIG.get_or_init(|| {
-        config_result.unwrap_or(Config {
-            vibration_strength: 1.0,
-            log_level: LevelFilter::Error,
-        })
-    });
+    let config = CONFIG.get_or_init(|| config_result.unwrap_or_default());
 
-    if let Ok(logfile) = std::fs::File::create("acprmod.log") {
-        simplelog::WriteLogger::init(
-            config.log_level,
-            simplelog::ConfigBuilder::default()
-                .set_location_level(simplelog::LevelFilter::Off)
-                .build(),
-            logfile,
-        )
-        .unwrap();
+    // a bare file name has an empty parent, which doesn't need creating
+    if let Some(parent) = config.log_file.parent() {
+        if !parent.as_os_str().is_empty() {
+            let _ = std::fs::create_dir_all(parent);
+        }
+    }
+
+    if let Ok(logfile) = std::fs::File::create(&config.log_file) {
+        setup_logging(config, logfile);
+    }
+
+    eventlog::info("acpr_buttplug started");
+
+    if let Some(warning) = config_warning {
+        log::warn!("{warning}");
     }
 
     std::panic::set_hook(Box::new(|e| {
         log::error!("panicked!: {e}");
     }));
 
+    // load offsets now so the detected game version ends up in the log
+    offsets::get();
+
+    if config.persist_enabled_state {
+        enabled::restore();
+    }
+
+    known_devices::load();
+
+    // runs while the other threads are still alive, unlike DLL_PROCESS_DETACH
+    unsafe { hooks::setup_exit_hook() };
+
     tokio::runtime::Builder::new_current_thread()
         .enable_all()
         .build()
@@ -85,63 +848,256 @@ pub fn initialize() {
         .block_on(run())
 }
 
-fn setup_config() -> Result<Config, String> {
-    let config_path = PathBuf::from("./acpr_buttplug_config.toml");
+/// Log to `logfile` at the configured level, with separate loggers for modules that override it
+fn setup_logging(config: &Config, logfile: std::fs::File) {
+    fn log_config() -> simplelog::ConfigBuilder {
+        let mut builder = simplelog::ConfigBuilder::default();
+        builder.set_location_level(simplelog::LevelFilter::Off);
+        builder
+    }
 
-    if !config_path.exists() {
-        if let Ok(mut f) = std::fs::File::create(&config_path) {
-            f.write_all(DEFAULT_CONFIG.as_bytes())
-                .map_err(|e| e.to_string())?
+    let mut default_config = log_config();
+    let mut loggers: Vec<Box<dyn simplelog::SharedLogger>> = Vec::new();
+
+    for (module, level) in &config.log_levels {
+        // modules of this mod can be given without the crate name
+        let targets = [
+            module.clone(),
+            format!("{}::{module}", env!("CARGO_CRATE_NAME")),
+        ];
+
+        let mut module_config = log_config();
+        for target in targets {
+            default_config.add_filter_ignore(target.clone());
+            module_config.add_filter_allow(target);
+        }
+
+        match logfile.try_clone() {
+            Ok(file) => loggers.push(simplelog::WriteLogger::new(
+                *level,
+                module_config.build(),
+                file,
+            )),
+            Err(e) => println!("error setting up log level for {module}: {e}"),
         }
     }
 
-    std::fs::File::open(&config_path)
-        .map_err(|e| e.to_string())
-        .and_then(|mut f| {
-            let mut s = String::new();
-            f.read_to_string(&mut s).map_err(|e| e.to_string())?;
-            toml::from_str::<Config>(&s).map_err(|e| e.to_string())
-        })
+    loggers.push(simplelog::WriteLogger::new(
+        config.log_level,
+        default_config.build(),
+        logfile,
+    ));
+
+    if config.event_log {
+        if let Some(logger) = eventlog::open() {
+            loggers.push(Box::new(logger));
+        }
+    }
+
+    simplelog::CombinedLogger::init(loggers).unwrap();
 }
 
-static DEVICES: Lazy<Mutex<Vec<Arc<ButtplugClientDevice>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// Called when the DLL is unloaded. When the whole process is terminating every other thread is
+/// already gone, including the one running the buttplug client, and one of them may have died
+/// holding the loggers lock, so that is left to the `ExitProcess` hook calling `before_exit`
+pub fn shutdown(process_terminating: bool) {
+    if !process_terminating {
+        before_exit();
+    }
+}
 
-async fn run() {
-    log::info!("setting up buttplug.rs...");
+/// Stop the devices and log the session summary while the other threads are still running
+pub fn before_exit() {
+    stop_devices_on_exit();
+    stats::log_summary();
+}
+
+/// Stop every device so none are left vibrating after the game exits,
+/// giving up after `SHUTDOWN_STOP_TIMEOUT` so a stuck device can't keep the game from closing
+fn stop_devices_on_exit() {
+    let runtime = match tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+    {
+        Ok(runtime) => runtime,
+        Err(e) => {
+            log::error!("error stopping devices on exit: {e}");
+            return;
+        }
+    };
 
-    let builder = ButtplugServerBuilder::default()
-        .comm_manager(BtlePlugCommunicationManagerBuilder::default())
-        .comm_manager(LovenseHIDDongleCommunicationManagerBuilder::default())
-        .comm_manager(XInputDeviceCommunicationManagerBuilder::default())
-        .finish();
+    runtime.block_on(async {
+        let stop_all = async {
+            let devices = DEVICES.lock().await.clone();
+            let results = futures::future::join_all(devices.iter().map(|dev| dev.stop())).await;
 
-    if let Err(e) = builder {
-        log::error!("error building server: {e}");
-        return;
+            for (dev, result) in devices.iter().zip(results) {
+                if let Err(e) = result {
+                    log::error!("error stopping {} on exit: {e}", dev.name());
+                }
+            }
+        };
+
+        if tokio::time::timeout(SHUTDOWN_STOP_TIMEOUT, stop_all)
+            .await
+            .is_err()
+        {
+            log::warn!("timed out stopping devices on exit");
+        }
+    });
+}
+
+/// Load the config, creating it with the defaults if it doesn't exist yet.
+/// When the file can't be written or read the defaults are used, along with a warning to log
+fn setup_config() -> Result<(Config, Option<String>), String> {
+    let config_path = PathBuf::from("./acpr_buttplug_config.toml");
+
+    if !config_path.exists() {
+        if let Err(e) = std::fs::write(&config_path, DEFAULT_CONFIG) {
+            let warning = format!(
+                "couldn't create {}: {}, using the default config",
+                config_path.display(),
+                describe_io_error(&e)
+            );
+            return Ok((default_config(), Some(warning)));
+        }
     }
 
-    log::trace!("server built");
+    let s = match std::fs::read_to_string(&config_path) {
+        Ok(s) => s,
+        Err(e) => {
+            let warning = format!(
+                "couldn't read {}: {}, using the default config",
+                config_path.display(),
+                describe_io_error(&e)
+            );
+            return Ok((default_config(), Some(warning)));
+        }
+    };
+
+    let mut config = toml::from_str::<Config>(&s).map_err(|e| e.to_string())?;
+    config.apply_sections();
+    config.check()?;
+    let warning = config.validate();
+
+    Ok((config, warning))
+}
+
+/// The config as written to a fresh config file
+fn default_config() -> Config {
+    let mut config: Config =
+        toml::from_str(DEFAULT_CONFIG).expect("default config should be valid");
+    config.apply_sections();
+    config
+}
 
-    let connector = ButtplugInProcessClientConnectorBuilder::default()
-        .server(builder.unwrap())
-        .finish();
+fn describe_io_error(e: &std::io::Error) -> String {
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    const ERROR_LOCK_VIOLATION: i32 = 33;
 
-    let client = ButtplugClient::new("Buttplug Mod");
-    if let Err(e) = client.connect(connector).await {
-        log::debug!("error connecting: {}", e)
+    match e.raw_os_error() {
+        Some(ERROR_SHARING_VIOLATION | ERROR_LOCK_VIOLATION) => {
+            "the file is locked by another program".to_string()
+        }
+        _ if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            "the file or folder is read-only".to_string()
+        }
+        _ => e.to_string(),
     }
+}
+
+static DEVICES: Lazy<Mutex<Vec<Arc<ButtplugClientDevice>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// Devices pinned to a fixed intensity from the console, by device name
+static PINNED_DEVICES: Lazy<std::sync::Mutex<HashMap<String, f64>>> =
+    Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// Smallest change in intensity that is sent to a device
+const INTENSITY_EPSILON: f64 = 0.005;
+const SHUTDOWN_STOP_TIMEOUT: Duration = Duration::from_secs(1);
+const BATTERY_POLL_INTERVAL: Duration = Duration::from_secs(60);
+const RESYNC_INTERVAL: Duration = Duration::from_secs(30);
+const TEST_PULSE_INTENSITY: f64 = 0.3;
+const TEST_PULSE_DURATION: Duration = Duration::from_millis(400);
+
+async fn run() {
+    log::info!("setting up buttplug.rs...");
+
+    let config = CONFIG.get().expect("config should exist");
+    connection::configure(
+        config.connection_kind(),
+        &config.websocket_url,
+        config.comm_managers,
+    );
+
+    // devices connecting right away may already be sent a test pulse
+    dispatch::spawn_dispatcher();
 
+    let client = Arc::new(ButtplugClient::new(&config.client_name));
     let mut events = client.event_stream();
+    let event_client = client.clone();
     tokio::spawn(async move {
         while let Some(event) = events.next().await {
             match event {
                 ButtplugClientEvent::DeviceAdded(device) => {
                     log::info!("Device {} Connected!", device.name());
+                    eventlog::info(&format!("device {} connected", device.name()));
+                    stats::record_device(device.name());
+
+                    // disabled devices stay connected, but are silenced once here and skipped afterwards
+                    let config = CONFIG.get().expect("config should exist");
+
+                    let known = is_known_device(config, device.name());
+                    if !known && config.ignore_unknown_devices {
+                        log::info!(
+                            "ignoring unknown device {}, add it under [devices] in the config to use it",
+                            device.name()
+                        );
+                        if let Err(e) = device.stop().await {
+                            log::error!("error stopping ignored device: {e}");
+                        }
+                        continue;
+                    }
+                    log::debug!(
+                        "{} is {}",
+                        device.name(),
+                        if known {
+                            "a known device"
+                        } else {
+                            "a new device"
+                        }
+                    );
+
+                    match config.device(device.name()).strength {
+                        Some(strength) => {
+                            log::debug!("{} uses its own strength {strength}", device.name())
+                        }
+                        None => log::debug!(
+                            "{} uses vibration_strength {}",
+                            device.name(),
+                            config.vibration_strength
+                        ),
+                    }
+
+                    if !config.device(device.name()).enabled {
+                        log::info!("Device {} is disabled in the config", device.name());
+                        if let Err(e) = device.stop().await {
+                            log::error!("error stopping disabled device: {e}");
+                        }
+                    }
+
+                    check_battery(&device).await;
+
+                    if config.test_on_connect {
+                        tokio::spawn(test_pulse(device.clone()));
+                    }
+
                     let mut devices = DEVICES.lock().await;
                     devices.push(device);
                 }
                 ButtplugClientEvent::DeviceRemoved(removed) => {
                     log::info!("Device {} Removed!", removed.name());
+                    eventlog::info(&format!("device {} removed", removed.name()));
+                    battery::forget_device(&removed);
                     let mut devices = DEVICES.lock().await;
 
                     // clear the device from our device list
@@ -155,134 +1111,1839 @@ async fn run() {
                     disconnected_devices.into_iter().for_each(|idx| {
                         devices.remove(idx);
                     });
+                    // while the list is still locked, so nothing sends to the device after this
+                    dispatch::forget(&removed);
+                }
+                ButtplugClientEvent::ServerDisconnect => {
+                    for dev in DEVICES.lock().await.drain(..) {
+                        dispatch::forget(&dev);
+                    }
+
+                    if connection::is_idle() {
+                        log::info!("disconnected from buttplug server while idle");
+                        continue;
+                    }
+
+                    log::warn!("disconnected from buttplug server");
+
+                    let config = CONFIG.get().expect("config should exist");
+                    tokio::spawn(connection::reconnect(
+                        event_client.clone(),
+                        config.max_reconnect_attempts,
+                    ));
+                }
+                ButtplugClientEvent::ScanningFinished => {
+                    log::debug!("scanning finished");
+
+                    let config = CONFIG.get().expect("config should exist");
+                    let device_count = DEVICES.lock().await.len();
+                    tokio::spawn(connection::scan_for_missing_devices(
+                        event_client.clone(),
+                        device_count,
+                        config.expected_devices,
+                    ));
                 }
                 _ => {}
             }
         }
     });
 
-    if let Err(e) = client.start_scanning().await {
-        log::error!("error scanning for devices: {e}")
-    }
-
-    let (tx, rx) = std::sync::mpsc::channel::<Event>();
-    // set up channels for communication between hook threads and event loop
-    {
-        let mut channel = HIT_CHANNEL_TX.lock().await;
-        *channel = Some(tx);
-    }
-
-    unsafe {
-        // currently broken due to some compiler optimization?
-        // game crashes on hit when built in release mode
-        // hooks::setup_hooks();
+    // keep trying in the background, Intiface Central may not have been started yet
+    if !connection::connect(&client).await {
+        tokio::spawn(connection::reconnect(
+            client.clone(),
+            config.max_reconnect_attempts,
+        ));
     }
 
-    let mut stopped_vibration = false;
-    loop {
-        tokio::time::sleep(Duration::from_millis(7)).await;
-        let hitstop = unsafe { get_current_hitstop() / 2 };
-        let in_hitstun = unsafe { either_player_in_hitstop() };
-        if hitstop == 0 {
-            if stopped_vibration {
-                continue;
-            }
-
-            let mut vibes = Vec::new();
-            for dev in DEVICES.lock().await.iter() {
-                vibes.push(stop_vibration(dev.clone()));
+    if config.rescan_interval_secs > 0 {
+        let rescan_client = client.clone();
+        let rescan_interval = Duration::from_secs(config.rescan_interval_secs);
+        tokio::spawn(async move {
+            loop {
+                sleep(rescan_interval).await;
+                let device_count = DEVICES.lock().await.len();
+                connection::scan_for_missing_devices(
+                    rescan_client.clone(),
+                    device_count,
+                    config.expected_devices,
+                )
+                .await;
             }
+        });
+    }
 
-            for vibe in vibes {
-                vibe.await
-            }
-            stopped_vibration = true;
-            continue;
+    let resync_client = client.clone();
+    tokio::spawn(async move {
+        loop {
+            sleep(RESYNC_INTERVAL).await;
+            resync_devices(&resync_client).await;
         }
+    });
 
-        stopped_vibration = false;
-
-        let mut intensity = hitstop_to_vibe_intensity(hitstop.into());
+    tokio::spawn(async {
+        loop {
+            sleep(BATTERY_POLL_INTERVAL).await;
 
-        // if a move was blocked, we make the vibration less intense
-        if !in_hitstun {
-            intensity /= 2.0
+            let devices = DEVICES.lock().await.clone();
+            for dev in devices {
+                check_battery(&dev).await;
+            }
         }
+    });
 
-        log::trace!("vibrating at {intensity}");
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel::<Event>();
+    // set up channels for communication between hook threads, the console and event loop
+    let _ = CHANNEL_TX.set(tx.clone());
+    let _ = HIT_CHANNEL_TX.set(tx);
 
-        let mut vibes = Vec::new();
-        for dev in DEVICES.lock().await.iter() {
-            vibes.push(vibrate_device(dev.clone(), intensity));
-        }
+    if config.enable_hooks {
+        // crashed the game on hit in release builds before the register fix in hooks,
+        // stays opt in until it has seen more real matches
+        log::warn!("hooks enabled, disable enable_hooks if the game crashes on hit");
+        unsafe { hooks::setup_hooks() };
+    } else {
+        log::info!("hooks disabled, hits are detected by polling only");
+    }
 
-        for vibe in vibes {
-            vibe.await
-        }
+    if config.console {
+        console::spawn_console();
+    }
 
-        continue;
+    if let Some(port) = config.master_intensity_osc_port {
+        osc::spawn_master_intensity_listener(port, &config.master_intensity_osc_address);
     }
-}
 
-fn hitstop_to_vibe_intensity(hitstop: f64) -> f64 {
-    // highest possible hitstop for a normal is 27f
-    // moving this to 28 allows edge cases to have a bigger response
-    // anything above 1.0 gets clamped down to the range 0-1 for vibration
-    (hitstop / 28.0).clamp(0.0, 1.0)
-}
+    let hit_confirmation_window = Duration::from_millis(config.hit_confirmation_window_ms);
+    let hit_dedup_window = Duration::from_millis(config.hit_dedup_window_ms);
 
-unsafe fn get_current_hitstop() -> u8 {
-    let player1_addr = PLAYER_1_STATE.get_address() as *const *const u8;
-    let player2_addr = PLAYER_2_STATE.get_address() as *const *const u8;
+    let mut stopped_vibration = false;
+    // intensity last sent to each device by index, cleared whenever devices are stopped
+    let mut last_sent: HashMap<u32, f64> = HashMap::new();
+    let mut focus_lost = false;
+    let mut game_paused = false;
+    let vibration_budget = (config.session_vibration_budget_secs > 0)
+        .then(|| Duration::from_secs(config.session_vibration_budget_secs));
+    let mut vibration_time = Duration::ZERO;
+    let mut budget_exhausted = false;
+    let wave_stagger = Duration::from_millis(config.wave_stagger_ms);
+    let mut wave_start: Option<Instant> = None;
+    let match_point_window = Duration::from_millis(config.match_point_boost_ms);
+    let mut match_point_ko: Option<Instant> = None;
+    let mut unreadable_ticks: u32 = 0;
+    let mut deadman_tripped = false;
+    let mut last_round_state: Option<u8> = None;
+    let mut round_reset = false;
+    // inverted, a stuck hitstop means less vibration instead of more, so there is nothing to cap
+    let max_continuous = (config.max_continuous_ms > 0 && !config.invert)
+        .then(|| Duration::from_millis(config.max_continuous_ms));
+    let mut vibrating_since: Option<Instant> = None;
+    let mut continuous_capped = false;
+    // intensity sent on the last poll, smoothed toward the computed intensity by ramp_rate
+    let mut output = 0.0;
+    // index of the device the current hit is sent to when using round robin
+    let mut hit_index: usize = 0;
+    // whether the game was already in hitstop on the last poll
+    let mut in_hitstop = false;
+    // when the hook last reported a hit that polling hasn't seen yet
+    let mut pending_hook_hit: Option<Instant> = None;
+    // when the last hook hit that wasn't a duplicate was received
+    let mut last_hook_hit: Option<Instant> = None;
+    // last known health of both players and which of them landed the most recent hit
+    let mut last_health: [Option<i16>; 2] = [None, None];
+    let mut attacker: Option<usize> = None;
+    // hits landed in a row by the current attacker
+    let mut streak: u32 = 0;
+    // pattern that overrides the hitstop driven vibration while it plays
+    let mut pattern: Option<PatternPlayer> = None;
 
-    if (*player1_addr).is_null() || (*player2_addr).is_null() {
-        return 0;
+    if config.match_point_boost > 0.0
+        && (offsets::get().health.is_none() || offsets::get().rounds_won.is_none())
+    {
+        log::warn!(
+            "match_point_boost needs both a health and a rounds_won offset, match point hits won't be boosted"
+        );
     }
 
-    let p1_hitstop = (*player1_addr).offset(0xFD).read_unaligned();
-    let p2_hitstop = (*player2_addr).offset(0xFD).read_unaligned();
-
-    p1_hitstop.max(p2_hitstop)
-}
-
-unsafe fn either_player_in_hitstop() -> bool {
-    let player1_addr = PLAYER_1_STATE.get_address() as *const *const u8;
-    let player2_addr = PLAYER_2_STATE.get_address() as *const *const u8;
+    if config.hit_type_settings.contains_key(&HitType::Super) && offsets::get().super_flag.is_none()
+    {
+        log::warn!(
+            "hit_types.super needs a super_flag offset, every hit counts as normal without one"
+        );
+    }
 
-    if (*player1_addr).is_null() || (*player2_addr).is_null() {
-        return false;
+    if config.intensity_source == IntensitySource::Hitstun && offsets::get().hitstun.is_none() {
+        log::warn!(
+            "intensity_source is hitstun but there is no hitstun offset, devices won't vibrate"
+        );
     }
 
-    let p1_in_hitstun = ((*player1_addr).offset(0xC).read_unaligned() | 0b000001) != 0;
-    let p2_in_hitstun = ((*player2_addr).offset(0xC).read_unaligned() | 0b000001) != 0;
+    let hit_rate_window = Duration::from_millis(config.hit_rate_window_ms);
+    let mut recent_hits: VecDeque<Instant> = VecDeque::new();
 
-    p1_in_hitstun || p2_in_hitstun
-}
+    let mut telemetry = match config.telemetry {
+        TelemetryMode::Off => None,
+        _ => match Telemetry::open(
+            &config.telemetry_path,
+            config.telemetry_max_size_mb * 1024 * 1024,
+        ) {
+            Ok(telemetry) => Some(telemetry),
+            Err(e) => {
+                log::error!("error opening telemetry file: {e}");
+                None
+            }
+        },
+    };
 
-async fn vibrate_device(dev: Arc<ButtplugClientDevice>, strength: f64) {
-    let config = CONFIG.get().expect("config should exist");
+    let mut light = config.light_port.as_ref().and_then(|port| {
+        match Light::open(
+            port,
+            config.light_baud_rate,
+            config.light_protocol,
+            config.light_channel,
+        ) {
+            Ok(light) => Some(light),
+            Err(e) => {
+                log::error!("error opening light serial port {port}: {e}");
+                None
+            }
+        }
+    });
 
-    if dev.message_attributes().scalar_cmd().is_some() {
-        if let Err(e) = dev
-            .vibrate(&VibrateCommand::Speed(
-                (strength * config.vibration_strength).clamp(0.0, 1.0),
-            ))
-            .await
+    let osc_output = config
+        .osc
+        .as_ref()
+        .and_then(|osc| match OscOutput::open(osc) {
+            Ok(output) => {
+                log::info!(
+                    "sending intensity over OSC to {}:{} at {}",
+                    osc.address,
+                    osc.port,
+                    osc.parameter
+                );
+                Some(output)
+            }
+            Err(e) => {
+                log::error!(
+                    "error opening OSC output to {}:{}: {e}",
+                    osc.address,
+                    osc.port
+                );
+                None
+            }
+        });
+
+    let overlay = match config.overlay_port {
+        Some(port) => match Overlay::start(port).await {
+            Ok(overlay) => {
+                log::info!("overlay websocket listening on ws://127.0.0.1:{port}");
+                Some(overlay)
+            }
+            Err(e) => {
+                log::error!("error starting overlay websocket on port {port}: {e}");
+                None
+            }
+        },
+        None => None,
+    };
+
+    let idle_timeout =
+        (config.idle_disconnect_secs > 0).then(|| Duration::from_secs(config.idle_disconnect_secs));
+    let mut last_activity = Instant::now();
+
+    let mut was_blocking_super = false;
+    let mut was_thrown = [false, false];
+    let mut was_instant_blocking = [false, false];
+    let mut was_faultless_defending = [false, false];
+    let mut was_super_flashing = [false, false];
+    let mut was_attacking = [false, false];
+    let mut was_knocked_down = [false, false];
+    let mut attack_connected = [false, false];
+    let mut last_tension: [Option<i32>; 2] = [None, None];
+    let mut hit_type = HitType::Normal;
+
+    let poll_interval = Duration::from_millis(config.poll_interval_ms);
+
+    let mut emergency_stop = parse_hotkey(&config.emergency_stop_key, "emergency_stop_key");
+    let mut toggle = parse_hotkey(&config.toggle_key, "toggle_key");
+
+    // scripted hitstop peaks at max_hitstop after it gets halved below
+    let simulation = config.simulate.then(|| {
+        log::warn!("simulate is on, playing scripted hits instead of reading the game");
+        Simulation::new((config.max_hitstop * 2.0).clamp(1.0, 255.0) as u8)
+    });
+
+    // until the players have loaded the game isn't read, everything else keeps running
+    let mut game_ready = simulation.is_some();
+    let game_load_timeout = Duration::from_secs(config.game_load_timeout_secs);
+    let game_load_started = Instant::now();
+
+    let mut last_tick = Instant::now();
+    loop {
+        tokio::time::sleep(poll_interval).await;
+        let tick_duration = last_tick.elapsed();
+        last_tick = Instant::now();
+
+        if !game_ready {
+            game_ready = game_state_ready(game_load_started, game_load_timeout);
+        }
+
+        let health = match (game_ready, offsets::get().health) {
+            (true, Some(offset)) => unsafe {
+                [
+                    read_health(&offsets::get().player1_state, offset),
+                    read_health(&offsets::get().player2_state, offset),
+                ]
+            },
+            _ => [None, None],
+        };
+
+        // whoever just lost health was hit by the other player
+        for (player, (current, last)) in health.iter().zip(last_health).enumerate() {
+            if let (Some(current), Some(last)) = (current, last) {
+                if *current < last {
+                    // a streak lasts until the other player lands a hit
+                    if attacker == Some(1 - player) {
+                        streak += 1;
+                    } else {
+                        streak = 1;
+                    }
+                    attacker = Some(1 - player);
+                }
+            }
+        }
+
+        let mut events: Vec<Event> = std::iter::from_fn(|| rx.try_recv().ok()).collect();
+        if let Some(ko) = detect_ko(health, last_health) {
+            // the hit that caused the KO is still playing out, so it gets the boost,
+            // as long as it is the one that wins the match
+            let decides_match = match (&ko, attacker, offsets::get().rounds_won) {
+                (Event::Ko, Some(winner), Some(offset)) => unsafe {
+                    wins_match(winner, offset, config.rounds_to_win)
+                },
+                _ => false,
+            };
+            if decides_match {
+                log::debug!("match point KO");
+                match_point_ko = Some(Instant::now());
+            }
+            events.push(ko);
+        }
+        last_health = health;
+
+        if let (true, Some(super_offset), Some(blocking_offset)) = (
+            game_ready,
+            offsets::get().super_flag,
+            offsets::get().blocking,
+        ) {
+            let blocking_super = unsafe { is_super_blocked(super_offset, blocking_offset) };
+            if blocking_super && !was_blocking_super {
+                events.push(Event::BlockedSuper);
+            }
+            was_blocking_super = blocking_super;
+        }
+
+        if let (true, Some(offset)) = (game_ready, offsets::get().thrown) {
+            let thrown = unsafe { read_player_flags(offset) };
+
+            for (player, (thrown, was_thrown)) in thrown.iter().zip(was_thrown).enumerate() {
+                if *thrown && !was_thrown {
+                    events.push(Event::Throw {
+                        thrower: 1 - player,
+                    });
+                }
+            }
+            was_thrown = thrown;
+        }
+
+        if let (true, Some(offset)) = (game_ready, offsets::get().instant_block) {
+            let instant_block = unsafe { read_player_flags(offset) };
+            if started(instant_block, was_instant_blocking) {
+                events.push(Event::InstantBlock);
+            }
+            was_instant_blocking = instant_block;
+        }
+
+        if let (true, Some(offset)) = (game_ready, offsets::get().faultless_defense) {
+            let faultless_defense = unsafe { read_player_flags(offset) };
+            if started(faultless_defense, was_faultless_defending) {
+                events.push(Event::FaultlessDefense);
+            }
+            was_faultless_defending = faultless_defense;
+        }
+
+        if let (true, Some(offset)) = (game_ready, offsets::get().super_flash) {
+            let super_flash = unsafe { read_player_flags(offset) };
+            if started(super_flash, was_super_flashing) {
+                events.push(Event::SuperFlash);
+            }
+            was_super_flashing = super_flash;
+        }
+
+        if let (true, Some(offset)) = (
+            config.whiff_strength > 0.0 && game_ready,
+            offsets::get().attacking,
+        ) {
+            let attacking = unsafe { read_player_flags(offset) };
+
+            for ((attacking, was_attacking), connected) in attacking
+                .iter()
+                .zip(was_attacking)
+                .zip(attack_connected.iter_mut())
+            {
+                // any hitstop during the attack means it hit or was blocked
+                if *attacking && in_hitstop {
+                    *connected = true;
+                }
+
+                if was_attacking && !attacking {
+                    if !*connected {
+                        events.push(Event::Whiff);
+                    }
+                    *connected = false;
+                }
+            }
+            was_attacking = attacking;
+        }
+
+        if let (true, Some(offset)) = (
+            config.knockdown_strength > 0.0 && game_ready,
+            offsets::get().knockdown,
+        ) {
+            let knocked_down = unsafe { read_player_flags(offset) };
+            if started(knocked_down, was_knocked_down) {
+                events.push(Event::Knockdown);
+            } else if started(was_knocked_down, knocked_down) {
+                events.push(Event::Wakeup);
+            }
+            was_knocked_down = knocked_down;
+        }
+
+        let tension = match (config.meter_mode, offsets::get().tension) {
+            (MeterMode::Off, _) | (_, None) => [None, None],
+            _ if !game_ready => [None, None],
+            (_, Some(offset)) => unsafe {
+                [
+                    read_tension(&offsets::get().player1_state, offset),
+                    read_tension(&offsets::get().player2_state, offset),
+                ]
+            },
+        };
+
+        if config.meter_mode == MeterMode::Change {
+            for (current, last) in tension.iter().zip(last_tension.iter_mut()) {
+                match (current, *last) {
+                    (Some(current), Some(previous)) => {
+                        if (current - previous).abs() >= config.meter_change_threshold {
+                            events.push(Event::MeterChanged);
+                            *last = Some(*current);
+                        }
+                    }
+                    // only remember the value a change was last reported at,
+                    // so slow gains still add up to a pulse
+                    _ => *last = *current,
+                }
+            }
+        }
+
+        let mut hit_events = 0;
+        let mut hook_hit_type = None;
+        for event in events {
+            match event {
+                Event::Hit(kind) => {
+                    // some moves run the hit routine several times for one visual hit
+                    let duplicate = last_hook_hit
+                        .map_or(false, |hit_time| hit_time.elapsed() < hit_dedup_window);
+
+                    if duplicate {
+                        log::trace!("ignoring duplicate hook hit");
+                    } else {
+                        hit_events += 1;
+                        hook_hit_type = Some(kind);
+                        last_hook_hit = Some(Instant::now());
+                    }
+                }
+                Event::ResyncDevices => resync_devices(&client).await,
+                Event::Rescan => {
+                    tokio::spawn(connection::rescan(
+                        client.clone(),
+                        config.max_reconnect_attempts,
+                    ));
+                }
+                Event::SimulatedHit {
+                    intensity,
+                    duration_ms,
+                } => {
+                    log::info!("simulating a hit at {intensity} for {duration_ms}ms");
+                    start_pattern(
+                        &mut pattern,
+                        vec![PatternStep {
+                            intensity,
+                            duration_ms,
+                        }],
+                        config.priorities.simulated_hit,
+                    );
+                }
+                Event::ExternalHit { intensity } => {
+                    log::debug!("external hit at {intensity}");
+                    start_pattern(
+                        &mut pattern,
+                        vec![PatternStep {
+                            intensity,
+                            duration_ms: config.external_hit_ms,
+                        }],
+                        config.priorities.external_hit,
+                    );
+                }
+                Event::PinDevice { name, intensity } => {
+                    last_sent.clear();
+                    // the device changed outside the polling loop, so the next idle tick sends to it again
+                    stopped_vibration = false;
+                    log::info!("pinning {name} at {intensity}");
+                    if let Ok(mut pinned) = PINNED_DEVICES.lock() {
+                        pinned.insert(name.clone(), intensity);
+                    }
+
+                    for dev in DEVICES.lock().await.iter() {
+                        if *dev.name() == name {
+                            dispatch::send(DeviceCommand::Vibrate(dev.clone(), intensity));
+                        }
+                    }
+                }
+                Event::ReleaseDevice { name } => {
+                    last_sent.clear();
+                    stopped_vibration = false;
+                    log::info!("releasing {name}");
+                    if let Ok(mut pinned) = PINNED_DEVICES.lock() {
+                        pinned.remove(&name);
+                    }
+
+                    for dev in DEVICES.lock().await.iter() {
+                        if *dev.name() == name {
+                            dispatch::send(rest_command(config, dev.clone()));
+                        }
+                    }
+                }
+                Event::Ko => {
+                    log::info!("KO!");
+                    start_pattern(
+                        &mut pattern,
+                        config.ko_pattern.clone(),
+                        config.priorities.ko,
+                    );
+                }
+                Event::DoubleKo => {
+                    log::info!("Double KO!");
+                    start_pattern(
+                        &mut pattern,
+                        config.double_ko_pattern.clone(),
+                        config.priorities.double_ko,
+                    );
+                }
+                Event::MeterChanged => {
+                    log::debug!("tension changed");
+                    start_pattern(
+                        &mut pattern,
+                        vec![PatternStep {
+                            intensity: config.meter_intensity,
+                            duration_ms: config.meter_pulse_ms,
+                        }],
+                        config.priorities.meter,
+                    );
+                }
+                Event::Throw { thrower } => {
+                    // without knowing which side the user plays every throw counts as dealt
+                    let taken = config.player != 0 && usize::from(config.player - 1) != thrower;
+                    log::info!("player {} threw player {}", thrower + 1, (1 - thrower) + 1);
+
+                    let throw_pattern = if taken {
+                        &config.thrown_pattern
+                    } else {
+                        &config.throw_pattern
+                    };
+                    start_pattern(&mut pattern, throw_pattern.clone(), config.priorities.throw);
+                }
+                Event::InstantBlock => {
+                    log::debug!("instant block");
+                    start_pattern(
+                        &mut pattern,
+                        config.instant_block_pattern.clone(),
+                        config.priorities.defense,
+                    );
+                }
+                Event::FaultlessDefense => {
+                    log::debug!("faultless defense");
+                    start_pattern(
+                        &mut pattern,
+                        config.faultless_defense_pattern.clone(),
+                        config.priorities.defense,
+                    );
+                }
+                Event::SuperFlash => {
+                    log::debug!("super flash");
+                    start_pattern(
+                        &mut pattern,
+                        config.super_flash_pattern.clone(),
+                        config.priorities.super_flash,
+                    );
+                }
+                Event::Knockdown => {
+                    log::debug!("knockdown");
+                    start_pattern(
+                        &mut pattern,
+                        decaying_pulses(
+                            config.knockdown_strength,
+                            config.knockdown_ms,
+                            config.knockdown_pulse_ms,
+                        ),
+                        config.priorities.knockdown,
+                    );
+                }
+                Event::Wakeup => {
+                    // the cue fades over knockdown_ms at most, getting up ends it early
+                    if pattern.as_ref().map_or(false, |playing| {
+                        playing.priority() == config.priorities.knockdown
+                    }) {
+                        log::debug!("wakeup, ending the knockdown cue");
+                        pattern = None;
+                    }
+                }
+                Event::Whiff => {
+                    log::trace!("whiff");
+                    start_pattern(
+                        &mut pattern,
+                        vec![PatternStep {
+                            intensity: config.whiff_strength,
+                            duration_ms: config.whiff_ms,
+                        }],
+                        config.priorities.whiff,
+                    );
+                }
+                Event::BlockedSuper => {
+                    log::info!("super blocked!");
+                    start_pattern(
+                        &mut pattern,
+                        config.blocked_super_pattern.clone(),
+                        config.priorities.blocked_super,
+                    );
+                }
+            }
+        }
+
+        if let Some(hotkey) = emergency_stop.as_mut() {
+            // only ever stops, feedback comes back through the toggle key or the console
+            if hotkey.pressed() {
+                log::warn!("emergency stop pressed, stopping all devices");
+                // pinned devices would otherwise keep going
+                if let Ok(mut pinned) = PINNED_DEVICES.lock() {
+                    pinned.clear();
+                }
+                // stopped_vibration can't be trusted here, a pinned device keeps going
+                // without game activity ever clearing it
+                for dev in DEVICES.lock().await.iter() {
+                    dispatch::send(DeviceCommand::Stop(dev.clone()));
+                }
+                last_sent.clear();
+                output = 0.0;
+                stopped_vibration = true;
+                enabled::set_enabled(false);
+            }
+        }
+
+        if let Some(hotkey) = toggle.as_mut() {
+            if hotkey.pressed() {
+                enabled::set_enabled(!enabled::is_enabled());
+            }
+        }
+
+        if !enabled::is_enabled() {
+            if !stopped_vibration {
+                for dev in DEVICES.lock().await.iter() {
+                    dispatch::send(DeviceCommand::Stop(dev.clone()));
+                }
+                last_sent.clear();
+                output = 0.0;
+                stopped_vibration = true;
+            }
+            continue;
+        }
+
+        if budget_exhausted {
+            continue;
+        }
+
+        // the hitstop freezes along with the game, so it can't be trusted while paused
+        if let Some(offset) = &offsets::get().paused {
+            if unsafe { is_game_paused(offset) } {
+                if !game_paused {
+                    log::info!("game paused, stopping all devices");
+                    for dev in DEVICES.lock().await.iter() {
+                        dispatch::send(rest_command(config, dev.clone()));
+                    }
+                    last_sent.clear();
+                    output = 0.0;
+                    pattern = None;
+                    game_paused = true;
+                    stopped_vibration = true;
+                }
+                continue;
+            } else if game_paused {
+                log::info!("game unpaused, resuming");
+                game_paused = false;
+            }
+        }
+
+        if config.pause_when_unfocused && simulation.is_none() && !helpers::game_has_focus() {
+            if !focus_lost {
+                log::info!("game lost focus, pausing");
+                for dev in DEVICES.lock().await.iter() {
+                    match config.focus_loss {
+                        FocusLoss::Stop => dispatch::send(DeviceCommand::Stop(dev.clone())),
+                        FocusLoss::Idle => dispatch::send(DeviceCommand::Vibrate(
+                            dev.clone(),
+                            config.idle_intensity,
+                        )),
+                    }
+                }
+                last_sent.clear();
+                output = 0.0;
+                focus_lost = true;
+                // whatever happens after regaining focus has to reset the devices
+                stopped_vibration = false;
+            }
+            continue;
+        } else if focus_lost {
+            log::info!("game regained focus, resuming");
+            focus_lost = false;
+        }
+
+        let (hitstop, player_hitstop) = match &simulation {
+            Some(simulation) => {
+                let hitstop = simulation.hitstop();
+                (hitstop, [Some(hitstop), Some(0)])
+            }
+            None if !game_ready => (0, [None, None]),
+            None => unsafe { get_current_hitstop(config.combine_mode) },
+        };
+        let hitstop = match config.player_mode {
+            PlayerMode::Both => hitstop,
+            PlayerMode::Player1 => player_hitstop[0].unwrap_or(0),
+            PlayerMode::Player2 => player_hitstop[1].unwrap_or(0),
+        } / 2;
+        let player_hitstop = player_hitstop.map(|hitstop| hitstop.map(|hitstop| hitstop / 2));
+
+        // a game that crashed or left a match mid hit can't be trusted to stop the devices
+        if player_hitstop.iter().all(Option::is_some) {
+            if deadman_tripped {
+                log::info!("game state readable again, resuming");
+            }
+            unreadable_ticks = 0;
+            deadman_tripped = false;
+        } else if game_ready {
+            unreadable_ticks = unreadable_ticks.saturating_add(1);
+        }
+
+        if config.deadman_ticks > 0 && unreadable_ticks >= config.deadman_ticks && !deadman_tripped
         {
+            log::warn!("game state unreadable for {unreadable_ticks} polls, stopping all devices");
+            for dev in DEVICES.lock().await.iter() {
+                dispatch::send(rest_command(config, dev.clone()));
+            }
+            last_sent.clear();
+            output = 0.0;
+            stopped_vibration = true;
+            deadman_tripped = true;
+        }
+        // only the game driven intensity is dropped, console hits, external hits and
+        // the idle disconnect keep working in the menus
+        let hitstop = if deadman_tripped { 0 } else { hitstop };
+
+        // a new round or the end of a match is a safe point to stop whatever the last hit left going
+        if let Some(offset) = &offsets::get().round_state {
+            let round_state = unsafe { read_round_state(offset) };
+            if round_state.is_some()
+                && last_round_state.is_some()
+                && round_state != last_round_state
+            {
+                log::info!(
+                    "round state changed from {:?} to {:?}, stopping all devices",
+                    last_round_state,
+                    round_state
+                );
+                for dev in DEVICES.lock().await.iter() {
+                    dispatch::send(rest_command(config, dev.clone()));
+                }
+                last_sent.clear();
+                output = 0.0;
+                pattern = None;
+                stopped_vibration = true;
+                round_reset = true;
+            }
+            if round_state.is_some() {
+                last_round_state = round_state;
+            }
+        }
+
+        // hitstop lingering from the last hit of the round doesn't start the devices again
+        if round_reset {
+            if hitstop > 0 {
+                continue;
+            }
+            round_reset = false;
+        }
+
+        let in_hitstun = game_ready && unsafe { any_player_in_hitstun(config.player_mode) };
+        let players_hit = if game_ready {
+            unsafe { players_in_hitstun() }
+        } else {
+            [false, false]
+        };
+        let hitstun = match (config.intensity_source, offsets::get().hitstun) {
+            (IntensitySource::Hitstun, Some(offset)) if game_ready && !deadman_tripped => unsafe {
+                get_remaining_hitstun(offset, config.player_mode)
+            },
+            _ => 0,
+        };
+
+        // hooks report hits on the exact frame, so they decide when a hit happened
+        let mut new_hits = hit_events;
+        if hit_events > 0 {
+            pending_hook_hit = Some(Instant::now());
+        }
+
+        // polling sees the hitstop a little later, if it follows a hook hit closely enough
+        // both are the same hit, otherwise it is a hit the hook didn't report
+        if hitstop > 0 && !in_hitstop {
+            if config.big_hit_hitstop > 0 && hitstop >= config.big_hit_hitstop {
+                log::debug!("big hit with {hitstop} hitstop");
+                start_pattern(
+                    &mut pattern,
+                    config.big_hit_pattern.clone(),
+                    config.priorities.big_hit,
+                );
+            }
+
+            let confirms_hook_hit = pending_hook_hit.take().map_or(false, |hit_time| {
+                hit_time.elapsed() <= hit_confirmation_window
+            });
+
+            if confirms_hook_hit {
+                log::trace!("hook hit confirmed by polled hitstop");
+            } else {
+                new_hits += 1;
+            }
+        }
+        in_hitstop = hitstop > 0;
+        stats::record_hits(new_hits);
+
+        if new_hits > 0 {
+            hit_type = hook_hit_type.unwrap_or_else(|| unsafe { current_hit_type() });
+            if let Some(settings) = config.hit_type_settings.get(&hit_type) {
+                if !settings.pattern.is_empty() {
+                    log::debug!("{hit_type:?} hit");
+                    start_pattern(
+                        &mut pattern,
+                        settings.pattern.clone(),
+                        config.priorities.hit_type,
+                    );
+                }
+            }
+        }
+
+        // a short pulse on every hit so fast strings of hits don't blur into one long buzz
+        if new_hits > 0 && config.hit_pulse_strength > 0.0 {
+            start_pattern(
+                &mut pattern,
+                vec![PatternStep {
+                    intensity: config.hit_pulse_strength,
+                    duration_ms: config.hit_pulse_ms,
+                }],
+                config.priorities.hit_pulse,
+            );
+        }
+
+        if config.intensity_source == IntensitySource::HitRate {
+            recent_hits.extend(std::iter::repeat(Instant::now()).take(new_hits));
+            while recent_hits
+                .front()
+                .map_or(false, |hit_time| hit_time.elapsed() > hit_rate_window)
+            {
+                recent_hits.pop_front();
+            }
+        }
+
+        if new_hits > 0 || hitstop > 0 {
+            last_activity = Instant::now();
+            if connection::is_idle() {
+                log::info!("game activity detected, reconnecting");
+                connection::wake(client.clone(), config.max_reconnect_attempts);
+            }
+        } else if let Some(timeout) = idle_timeout {
+            if !connection::is_idle() && last_activity.elapsed() >= timeout {
+                log::info!("no game activity for {}s, disconnecting", timeout.as_secs());
+                connection::idle_disconnect(client.clone());
+            }
+        }
+
+        if new_hits > 0 && config.device_mode == DeviceMode::Wave {
+            wave_start = Some(Instant::now());
+        }
+
+        if new_hits > 0 && config.device_mode == DeviceMode::RoundRobin {
+            // the previous device may still be vibrating from the last hit
+            let previous = select_devices(
+                &DEVICES.lock().await,
+                config.device_mode,
+                hit_index,
+                &config.wave_order,
+            );
+            for dev in previous {
+                last_sent.remove(&dev.index());
+                dispatch::send(rest_command(config, dev));
+            }
+            hit_index = hit_index.wrapping_add(new_hits);
+        }
+
+        let drive = match config.intensity_source {
+            IntensitySource::Hitstop => hitstop.into(),
+            IntensitySource::Hitstun => hitstun,
+            IntensitySource::HitRate => recent_hits.len() as u16,
+        };
+
+        let pattern_level = pattern
+            .as_ref()
+            .and_then(|player| player.intensity().map(|level| (level, player.priority())));
+        if pattern_level.is_none() {
+            pattern = None;
+        }
+
+        let meter_level = match config.meter_mode {
+            MeterMode::Level => tension.iter().flatten().max().map_or(0.0, |tension| {
+                tension_to_vibe_intensity(*tension, config.max_tension, config.meter_intensity)
+            }),
+            _ => 0.0,
+        };
+
+        // while ramping down the devices keep going until the output reaches zero,
+        // with an idle intensity they never stop and hum along underneath the hits instead
+        if drive == 0
+            && pattern_level.is_none()
+            && meter_level == 0.0
+            && config.idle_intensity == 0.0
+            && !config.invert
+            && output <= config.ramp_rate
+        {
+            output = 0.0;
+            vibrating_since = None;
+            continuous_capped = false;
+
+            if let Some(telemetry) = telemetry.as_mut() {
+                if config.telemetry == TelemetryMode::Tick {
+                    let devices = DEVICES
+                        .lock()
+                        .await
+                        .iter()
+                        .map(|dev| (dev.name().clone(), 0.0))
+                        .collect::<Vec<_>>();
+                    telemetry.record(0, player_hitstop, in_hitstun, 0.0, &devices);
+                }
+            }
+
+            if let Some(light) = light.as_mut() {
+                light.set_intensity(0.0);
+            }
+
+            if let Some(osc_output) = osc_output.as_ref() {
+                osc_output.set_intensity(0.0);
+            }
+
+            if let Some(overlay) = overlay.as_ref() {
+                overlay.update(OverlayState {
+                    intensity: 0.0,
+                    hitstop: 0,
+                    device_count: DEVICES.lock().await.len(),
+                });
+            }
+
+            if config.verbose_state {
+                unsafe { log_game_state(0.0) };
+            }
+
+            if stopped_vibration {
+                continue;
+            }
+
+            for dev in DEVICES.lock().await.iter() {
+                dispatch::send(DeviceCommand::Stop(dev.clone()));
+            }
+            last_sent.clear();
+            stopped_vibration = true;
+            continue;
+        }
+
+        stopped_vibration = false;
+
+        let continuous = if drive == 0 {
+            invert_intensity(0.0, config.invert)
+        } else {
+            let mapped = match config.intensity_source {
+                IntensitySource::Hitstop => {
+                    hitstop_to_vibe_intensity(hitstop.into(), config.max_hitstop, &config.curve)
+                }
+                IntensitySource::Hitstun => {
+                    hitstun_to_vibe_intensity(hitstun.into(), config.max_hitstun)
+                }
+                IntensitySource::HitRate => hit_rate_to_vibe_intensity(
+                    recent_hits.len() as f64 / hit_rate_window.as_secs_f64(),
+                    config.min_hit_rate,
+                    config.max_hit_rate,
+                ),
+            };
+            let mut intensity = invert_intensity(mapped, config.invert);
+
+            intensity *= config
+                .hit_type_settings
+                .get(&hit_type)
+                .map_or(1.0, |settings| settings.scale);
+
+            // if a move was blocked, we make the vibration less intense,
+            // the hit rate keeps going between hits so it isn't tied to the current one
+            if !in_hitstun && config.intensity_source != IntensitySource::HitRate {
+                intensity *= config.block_multiplier
+            }
+
+            if let Some(attacker) = attacker {
+                if let (Some(attacker_health), Some(defender_health)) =
+                    (health[attacker], health[1 - attacker])
+                {
+                    intensity *= health_differential_modifier(
+                        config.health_differential,
+                        config.health_differential_strength,
+                        attacker_health,
+                        defender_health,
+                        config.max_health,
+                    );
+                }
+            }
+
+            // hits hurt more the less health the player taking them has left
+            if let Some(defender_health) = attacker.and_then(|attacker| health[1 - attacker]) {
+                intensity *= desperation_modifier(
+                    defender_health,
+                    config.max_health,
+                    config.desperation_strength,
+                    &config.desperation_curve,
+                );
+            }
+
+            if let Some(offset) = offsets::get().combo {
+                let combo = unsafe { get_combo_count(offset) };
+                intensity *= match config.combo_mode {
+                    ComboMode::Off => 1.0,
+                    ComboMode::Decay => {
+                        combo_decay_modifier(combo, config.combo_decay, config.combo_decay_floor)
+                    }
+                    ComboMode::Escalate => combo_escalation_modifier(
+                        combo,
+                        config.combo_escalation,
+                        config.combo_escalation_cap,
+                    ),
+                };
+            }
+
+            if let Some(offset) = offsets::get().position_x {
+                if let Some(distance) = unsafe { get_player_distance(offset) } {
+                    intensity *=
+                        distance_modifier(distance, config.distance_weight, config.max_distance);
+                }
+            }
+
+            if match_point_ko.map_or(false, |ko_time| ko_time.elapsed() < match_point_window) {
+                intensity *= 1.0 + config.match_point_boost;
+            }
+
+            intensity * streak_modifier(streak, config.streak_bonus, config.streak_bonus_cap)
+        };
+        // the meter level and idle hum sit underneath hits rather than adding to them
+        let continuous = continuous.max(meter_level).max(config.idle_intensity);
+
+        let target = combine_layers(
+            continuous,
+            config.priorities.hitstop,
+            pattern_level,
+            config.ducking,
+        );
+        let intensity = ramp_toward(output, target, config.ramp_rate);
+        // what devices assigned to the player that isn't being hit still feel
+        let unhit_intensity = intensity.min(combine_layers(
+            meter_level.max(config.idle_intensity),
+            config.priorities.hitstop,
+            pattern_level,
+            config.ducking,
+        ));
+        output = intensity;
+
+        log::trace!(
+            "vibrating at {intensity}, hitstop p1={:?} p2={:?}",
+            player_hitstop[0],
+            player_hitstop[1]
+        );
+        stats::record_vibration(intensity, tick_duration);
+
+        if let Some(light) = light.as_mut() {
+            light.set_intensity(intensity);
+        }
+
+        if let Some(osc_output) = osc_output.as_ref() {
+            osc_output.set_intensity(intensity);
+        }
+
+        if let Some(overlay) = overlay.as_ref() {
+            overlay.update(OverlayState {
+                intensity,
+                hitstop,
+                device_count: DEVICES.lock().await.len(),
+            });
+        }
+
+        if config.verbose_state {
+            unsafe { log_game_state(intensity) };
+        }
+
+        // the idle hum doesn't count as vibrating, otherwise it would never get a break
+        if intensity > config.idle_intensity {
+            vibration_time += tick_duration;
+
+            // a paused or stuck game can hold a hitstop forever, only resume once the intensity drops to zero
+            let since = *vibrating_since.get_or_insert_with(Instant::now);
+            if max_continuous.map_or(false, |max| since.elapsed() >= max) {
+                if !continuous_capped {
+                    log::warn!(
+                        "vibrating for over {}ms without a break, stopping until the intensity drops to zero",
+                        config.max_continuous_ms
+                    );
+                    for dev in DEVICES.lock().await.iter() {
+                        dispatch::send(rest_command(config, dev.clone()));
+                    }
+                    last_sent.clear();
+                    output = 0.0;
+                    continuous_capped = true;
+                }
+                continue;
+            }
+        } else {
+            vibrating_since = None;
+            continuous_capped = false;
+        }
+
+        if let Some(budget) = vibration_budget {
+            if vibration_time >= budget {
+                let notice = format!(
+                    "session vibration budget of {}s used up, devices stay stopped until the game restarts",
+                    budget.as_secs()
+                );
+                log::warn!("{notice}");
+                if config.console {
+                    println!("{notice}");
+                }
+
+                for dev in DEVICES.lock().await.iter() {
+                    dispatch::send(DeviceCommand::Stop(dev.clone()));
+                }
+                last_sent.clear();
+                output = 0.0;
+                budget_exhausted = true;
+                continue;
+            }
+        }
+
+        let devices = select_devices(
+            &DEVICES.lock().await,
+            config.device_mode,
+            hit_index,
+            &config.wave_order,
+        );
+        let mut device_values = Vec::with_capacity(devices.len());
+        for (position, dev) in devices.into_iter().enumerate() {
+            // in a wave each device joins in a little after the one before it
+            let intensity = match wave_start {
+                Some(start) if start.elapsed() < wave_stagger * position as u32 => {
+                    config.idle_intensity
+                }
+                _ => intensity,
+            };
+
+            // a device assigned to a player only feels the hits that player takes
+            let assigned_player = config.device(dev.name()).player;
+            let hit = assigned_player
+                .and_then(|player| players_hit.get(player.checked_sub(1)? as usize))
+                .copied();
+            let intensity = match hit {
+                Some(false) => intensity.min(unhit_intensity),
+                _ => intensity,
+            };
+            device_values.push((dev.name().clone(), intensity));
+
+            // BLE devices fall behind when flooded with commands that don't change anything
+            let unchanged = last_sent
+                .get(&dev.index())
+                .map_or(false, |last| (last - intensity).abs() < INTENSITY_EPSILON);
+            if unchanged {
+                continue;
+            }
+
+            last_sent.insert(dev.index(), intensity);
+            dispatch::send(DeviceCommand::Vibrate(dev, intensity));
+        }
+
+        if let Some(telemetry) = telemetry.as_mut() {
+            if config.telemetry == TelemetryMode::Tick || new_hits > 0 {
+                telemetry.record(
+                    hitstop,
+                    player_hitstop,
+                    in_hitstun,
+                    intensity,
+                    &device_values,
+                );
+            }
+        }
+
+        continue;
+    }
+}
+
+/// Parse the key of a hotkey setting, an empty or missing key disables it
+fn parse_hotkey(key: &Option<String>, setting: &str) -> Option<Hotkey> {
+    let key = key.as_deref().filter(|key| !key.is_empty())?;
+
+    let hotkey = Hotkey::parse(key);
+    if hotkey.is_none() {
+        log::error!("unsupported {setting} {key}, use F1 to F24, a letter or a digit");
+    }
+    hotkey
+}
+
+/// Whether both players exist yet, the state pointers stay null through the menus and loading
+/// screens on launch. Stops waiting `timeout` after `started` and reads the game anyway
+fn game_state_ready(started: Instant, timeout: Duration) -> bool {
+    if unsafe { players_exist() } {
+        log::info!("game state readable after {}s", started.elapsed().as_secs());
+        return true;
+    }
+
+    if started.elapsed() >= timeout {
+        log::warn!(
+            "players still don't exist after {}s, polling anyway",
+            timeout.as_secs()
+        );
+        return true;
+    }
+
+    false
+}
+
+/// Move `current` toward `target` by at most `rate`, a rate of 1.0 or more jumps straight to the target
+fn ramp_toward(current: f64, target: f64, rate: f64) -> f64 {
+    if rate >= 1.0 {
+        return target;
+    }
+
+    current + (target - current).clamp(-rate, rate)
+}
+
+/// Whether a flag turned on for either player since the last poll
+fn started(flags: [bool; 2], last_flags: [bool; 2]) -> bool {
+    flags
+        .iter()
+        .zip(last_flags)
+        .any(|(flag, last)| *flag && !last)
+}
+
+/// Check for players being knocked out since the last poll
+fn detect_ko(health: [Option<i16>; 2], last_health: [Option<i16>; 2]) -> Option<Event> {
+    let knocked_out = |player: usize| {
+        matches!(
+            (health[player], last_health[player]),
+            (Some(current), Some(last)) if current <= 0 && last > 0
+        )
+    };
+
+    match (knocked_out(0), knocked_out(1)) {
+        (true, true) => Some(Event::DoubleKo),
+        (true, false) | (false, true) => Some(Event::Ko),
+        (false, false) => None,
+    }
+}
+
+/// Pick the devices that should respond to the current hit
+fn select_devices(
+    devices: &[Arc<ButtplugClientDevice>],
+    mode: DeviceMode,
+    hit_index: usize,
+    wave_order: &[String],
+) -> Vec<Arc<ButtplugClientDevice>> {
+    match mode {
+        DeviceMode::All => devices.to_vec(),
+        DeviceMode::Wave => {
+            // devices missing from the order go last, in the order they connected
+            let mut devices = devices.to_vec();
+            devices.sort_by_key(|dev| {
+                wave_order
+                    .iter()
+                    .position(|name| name == dev.name())
+                    .unwrap_or(usize::MAX)
+            });
+            devices
+        }
+        DeviceMode::RoundRobin if devices.is_empty() => Vec::new(),
+        DeviceMode::RoundRobin => vec![devices[hit_index % devices.len()].clone()],
+    }
+}
+
+/// Bring a device to rest between hits, it drops to the idle hum when `idle_intensity` is set
+/// and only stops when it isn't
+fn rest_command(config: &Config, dev: Arc<ButtplugClientDevice>) -> DeviceCommand {
+    if config.idle_intensity > 0.0 {
+        DeviceCommand::Vibrate(dev, config.idle_intensity)
+    } else {
+        DeviceCommand::Stop(dev)
+    }
+}
+
+fn hitstop_to_vibe_intensity(hitstop: f64, max_hitstop: f64, curve: &Curve) -> f64 {
+    // anything above 1.0 gets clamped down to the range 0-1 for vibration
+    curve.apply(hitstop / max_hitstop)
+}
+
+/// Flip a mapped intensity when `invert` is set, so rest vibrates fully and bigger hits vibrate less
+fn invert_intensity(intensity: f64, invert: bool) -> f64 {
+    if invert {
+        1.0 - intensity
+    } else {
+        intensity
+    }
+}
+
+fn hitstun_to_vibe_intensity(hitstun: f64, max_hitstun: f64) -> f64 {
+    (hitstun / max_hitstun).clamp(0.0, 1.0)
+}
+
+/// Intensity for a rate of hits per second, starting at `min_rate` and reaching full intensity at `max_rate`
+fn hit_rate_to_vibe_intensity(rate: f64, min_rate: f64, max_rate: f64) -> f64 {
+    if rate < min_rate {
+        return 0.0;
+    }
+
+    ((rate - min_rate) / (max_rate - min_rate).max(f64::EPSILON)).clamp(0.0, 1.0)
+}
+
+/// Constant intensity for a tension gauge, reaching `max_intensity` with a full gauge
+fn tension_to_vibe_intensity(tension: i32, max_tension: f64, max_intensity: f64) -> f64 {
+    (tension as f64 / max_tension).clamp(0.0, 1.0) * max_intensity
+}
+
+/// Multiplier that weakens hits the further apart the players are, point blank hits stay at full strength
+fn distance_modifier(distance: f64, weight: f64, max_distance: f64) -> f64 {
+    let spacing = (distance / max_distance).clamp(0.0, 1.0);
+    (1.0 - spacing * weight).clamp(0.0, 1.0)
+}
+
+/// Multiplier that grows with each hit landed without getting hit back
+fn streak_modifier(streak: u32, bonus: f64, cap: f64) -> f64 {
+    let extra_hits = streak.saturating_sub(1) as f64;
+    1.0 + (extra_hits * bonus).min(cap)
+}
+
+/// Multiplier that shrinks with each hit of a combo, mirroring the games damage scaling
+fn combo_decay_modifier(combo: u16, decay: f64, floor: f64) -> f64 {
+    let extra_hits = combo.saturating_sub(1) as i32;
+    decay.powi(extra_hits).max(floor)
+}
+
+/// Multiplier that grows with each hit of a combo by `escalation`, adding at most `cap`,
+/// the combo counter resetting when the combo drops resets it too
+fn combo_escalation_modifier(combo: u16, escalation: f64, cap: f64) -> f64 {
+    let extra_hits = combo.saturating_sub(1) as f64;
+    1.0 + (extra_hits * escalation).min(cap)
+}
+
+/// Multiplier that grows as the defender runs out of health, adding up to `strength` at zero health,
+/// `curve` shapes how quickly it builds up over the lost health
+fn desperation_modifier(
+    defender_health: i16,
+    max_health: i16,
+    strength: f64,
+    curve: &Curve,
+) -> f64 {
+    let health_lost = 1.0 - defender_health as f64 / max_health.max(1) as f64;
+    1.0 + strength * curve.apply(health_lost)
+}
+
+/// Multiplier for the intensity of a hit based on how far ahead the attacker is on health
+fn health_differential_modifier(
+    mode: HealthDifferential,
+    strength: f64,
+    attacker_health: i16,
+    defender_health: i16,
+    max_health: i16,
+) -> f64 {
+    let difference = (attacker_health as f64 - defender_health as f64) / max_health.max(1) as f64;
+
+    let difference = match mode {
+        HealthDifferential::Off => return 1.0,
+        HealthDifferential::Momentum => difference,
+        HealthDifferential::Comeback => -difference,
+    };
+
+    (1.0 + difference * strength).max(0.0)
+}
+
+/// Read the hitstop of the current hit, both players merged by `mode`, along with each players own hitstop,
+/// `None` for a player that doesn't exist yet
+unsafe fn get_current_hitstop(mode: CombineMode) -> (u8, [Option<u8>; 2]) {
+    let p1_hitstop = read_player_field::<u8>(&offsets::get().player1_state, offsets::get().hitstop);
+    let p2_hitstop = read_player_field::<u8>(&offsets::get().player2_state, offsets::get().hitstop);
+
+    let hitstop = match (p1_hitstop, p2_hitstop) {
+        (Some(p1_hitstop), Some(p2_hitstop)) => match mode {
+            CombineMode::Max => p1_hitstop.max(p2_hitstop),
+            CombineMode::Sum => p1_hitstop.saturating_add(p2_hitstop),
+            CombineMode::Average => ((p1_hitstop as u16 + p2_hitstop as u16) / 2) as u8,
+        },
+        _ => 0,
+    };
+
+    (hitstop, [p1_hitstop, p2_hitstop])
+}
+
+/// Whether any of the players `mode` reacts to is in hitstun, so the hit wasn't blocked
+unsafe fn any_player_in_hitstun(mode: PlayerMode) -> bool {
+    let [p1_hit, p2_hit] = players_in_hitstun();
+
+    match mode {
+        PlayerMode::Both => p1_hit || p2_hit,
+        PlayerMode::Player1 => p1_hit,
+        PlayerMode::Player2 => p2_hit,
+    }
+}
+
+/// Which players are in hitstun, a player that can't be read isn't
+unsafe fn players_in_hitstun() -> [bool; 2] {
+    [&offsets::get().player1_state, &offsets::get().player2_state].map(|player| {
+        read_player_field::<u8>(player, offsets::get().hitstun_flag).map_or(false, in_hitstun)
+    })
+}
+
+/// Whether a players raw state flags have the hitstun bit set
+fn in_hitstun(flags: u8) -> bool {
+    (flags & 0b000001) != 0
+}
+
+/// Log the raw state of both players and the resulting intensity on a single line
+unsafe fn log_game_state(intensity: f64) {
+    let describe = |player: &Offset| {
+        let hitstop = read_player_field::<u8>(player, offsets::get().hitstop);
+        let flags = read_player_field::<u8>(player, offsets::get().hitstun_flag);
+
+        match (hitstop, flags) {
+            (Some(hitstop), Some(flags)) => format!("[hitstop={hitstop} flags={flags:#010b}]"),
+            _ => "[null]".to_string(),
+        }
+    };
+
+    log::debug!(
+        "state p1={} p2={} intensity={intensity:.3}",
+        describe(&offsets::get().player1_state),
+        describe(&offsets::get().player2_state),
+    );
+}
+
+/// Read a value at `field_offset` inside a players state, returns `None` if the player doesn't exist yet
+/// or its memory isn't readable anymore
+unsafe fn read_player_field<T: Copy>(player: &Offset, field_offset: isize) -> Option<T> {
+    let player_addr = read_guarded(player.get_address() as *const *const u8)?;
+
+    if player_addr.is_null() {
+        return None;
+    }
+
+    read_guarded(player_addr.wrapping_offset(field_offset).cast::<T>())
+}
+
+/// Whether knocking out the other player wins `winner` the match. The game only counts a round
+/// as won once it ends, so on the KO the winner still has one round less than `rounds_to_win`
+unsafe fn wins_match(winner: usize, rounds_won_offset: isize, rounds_to_win: u8) -> bool {
+    let player = [&offsets::get().player1_state, &offsets::get().player2_state][winner];
+
+    read_player_field::<u8>(player, rounds_won_offset)
+        .map_or(false, |won| won.saturating_add(1) >= rounds_to_win)
+}
+
+/// Read the games round state, which changes when a round starts or ends and when the match is over
+unsafe fn read_round_state(offset: &Offset) -> Option<u8> {
+    read_guarded(offset.get_address() as *const u8)
+}
+
+/// Whether the game is paused, an unreadable flag counts as not paused
+unsafe fn is_game_paused(offset: &Offset) -> bool {
+    read_guarded::<u8>(offset.get_address() as *const u8).map_or(false, |flag| flag != 0)
+}
+
+/// Whether both player states have been created
+unsafe fn players_exist() -> bool {
+    [&offsets::get().player1_state, &offsets::get().player2_state]
+        .iter()
+        .all(|player| {
+            read_guarded(player.get_address() as *const *const u8)
+                .map_or(false, |addr| !addr.is_null())
+        })
+}
+
+/// Read a players current health, returns `None` if the player doesn't exist yet
+unsafe fn read_health(player: &Offset, health_offset: isize) -> Option<i16> {
+    read_player_field(player, health_offset)
+}
+
+/// Read the remaining hitstun of the players `mode` reacts to, the defender is the only one in hitstun
+unsafe fn get_remaining_hitstun(hitstun_offset: isize, mode: PlayerMode) -> u16 {
+    let p1_hitstun =
+        read_player_field::<u16>(&offsets::get().player1_state, hitstun_offset).unwrap_or(0);
+    let p2_hitstun =
+        read_player_field::<u16>(&offsets::get().player2_state, hitstun_offset).unwrap_or(0);
+
+    match mode {
+        PlayerMode::Both => p1_hitstun.max(p2_hitstun),
+        PlayerMode::Player1 => p1_hitstun,
+        PlayerMode::Player2 => p2_hitstun,
+    }
+}
+
+/// Whether a device has been used before, a device in the config counts as opted in
+/// even before it has been used
+fn is_known_device(config: &Config, name: &str) -> bool {
+    known_devices::is_known(name) || config.devices.contains_key(name)
+}
+
+/// Rebuild the device list from the client, in case added or removed events were missed.
+/// Unknown devices that were ignored when they connected stay out of the list
+async fn resync_devices(client: &ButtplugClient) {
+    let config = CONFIG.get().expect("config should exist");
+    let actual: Vec<_> = client
+        .devices()
+        .into_iter()
+        .filter(|dev| !config.ignore_unknown_devices || is_known_device(config, dev.name()))
+        .collect();
+    let mut devices = DEVICES.lock().await;
+
+    for dev in devices.iter() {
+        if !actual.iter().any(|actual| actual.index() == dev.index()) {
+            log::warn!("resync: removing stale device {}", dev.name());
+            dispatch::forget(dev);
+        }
+    }
+
+    for dev in &actual {
+        if !devices.iter().any(|known| known.index() == dev.index()) {
+            log::warn!("resync: adding missing device {}", dev.name());
+        }
+    }
+
+    *devices = actual;
+}
+
+/// Briefly vibrate a newly connected device to confirm it works
+async fn test_pulse(dev: Arc<ButtplugClientDevice>) {
+    log::info!("sending test pulse to {}", dev.name());
+
+    // through the dispatcher, so the pulse can't race commands from the polling loop
+    let config = CONFIG.get().expect("config should exist");
+    dispatch::send(DeviceCommand::Vibrate(dev.clone(), TEST_PULSE_INTENSITY));
+    sleep(TEST_PULSE_DURATION).await;
+    dispatch::send(rest_command(config, dev));
+}
+
+/// Read the current combo count, only the attacker has a running combo
+unsafe fn get_combo_count(combo_offset: isize) -> u16 {
+    let p1_combo =
+        read_player_field::<u16>(&offsets::get().player1_state, combo_offset).unwrap_or(0);
+    let p2_combo =
+        read_player_field::<u16>(&offsets::get().player2_state, combo_offset).unwrap_or(0);
+
+    p1_combo.max(p2_combo)
+}
+
+/// Check whether either player is performing a super while the other one blocks it
+unsafe fn is_super_blocked(super_offset: isize, blocking_offset: isize) -> bool {
+    let players = [&offsets::get().player1_state, &offsets::get().player2_state];
+    let flag = |player: &Offset, offset: isize| {
+        read_player_field::<u8>(player, offset).map_or(false, |flag| flag != 0)
+    };
+
+    (flag(players[0], super_offset) && flag(players[1], blocking_offset))
+        || (flag(players[1], super_offset) && flag(players[0], blocking_offset))
+}
+
+/// Classify a hit from the game state. The hit routines arguments haven't been decoded yet,
+/// so any hit while either player has a super active counts as a super
+pub unsafe fn current_hit_type() -> HitType {
+    match offsets::get().super_flag {
+        Some(offset) if read_player_flags(offset).contains(&true) => HitType::Super,
+        _ => HitType::Normal,
+    }
+}
+
+/// Read a flag byte inside both players states, a missing player counts as the flag being off
+unsafe fn read_player_flags(flag_offset: isize) -> [bool; 2] {
+    [
+        read_player_field::<u8>(&offsets::get().player1_state, flag_offset),
+        read_player_field::<u8>(&offsets::get().player2_state, flag_offset),
+    ]
+    .map(|flag| flag.map_or(false, |flag| flag != 0))
+}
+
+/// Read a players tension gauge, returns `None` if the player doesn't exist yet
+unsafe fn read_tension(player: &Offset, tension_offset: isize) -> Option<i32> {
+    read_player_field(player, tension_offset)
+}
+
+/// Read the horizontal distance between both players, `None` if either doesn't exist yet
+unsafe fn get_player_distance(position_offset: isize) -> Option<f64> {
+    let p1_x = read_player_field::<i32>(&offsets::get().player1_state, position_offset)?;
+    let p2_x = read_player_field::<i32>(&offsets::get().player2_state, position_offset)?;
+
+    Some((p1_x as f64 - p2_x as f64).abs())
+}
+
+/// Update the stored battery level of a device and warn once it is critically low
+async fn check_battery(dev: &ButtplugClientDevice) {
+    let config = CONFIG.get().expect("config should exist");
+
+    if let Some(level) = battery::update_battery_level(dev).await {
+        log::info!("{} battery at {:.0}%", dev.name(), level * 100.0);
+
+        if level < config.critical_battery_level {
+            log::warn!(
+                "{} battery critically low at {:.0}%, limiting intensity to {}",
+                dev.name(),
+                level * 100.0,
+                config.critical_battery_max_intensity
+            );
+        } else if level < config.low_battery_level {
+            log::warn!(
+                "{} battery low at {:.0}%, charge it before a long session",
+                dev.name(),
+                level * 100.0
+            );
+        }
+    }
+}
+
+/// Highest intensity a device may be driven at, reduced once its battery is critically low
+fn battery_intensity_cap(config: &Config, dev: &ButtplugClientDevice) -> f64 {
+    match battery::battery_level(dev) {
+        Some(level) if level < config.critical_battery_level => {
+            config.critical_battery_max_intensity
+        }
+        _ => 1.0,
+    }
+}
+
+/// Intensity a device was pinned to from the console, if any
+fn pinned_intensity(dev: &ButtplugClientDevice) -> Option<f64> {
+    PINNED_DEVICES.lock().ok()?.get(dev.name()).copied()
+}
+
+pub async fn vibrate_device(dev: Arc<ButtplugClientDevice>, strength: f64) {
+    let config = CONFIG.get().expect("config should exist");
+
+    // pinned devices ignore the game and all scaling until they are released
+    let device_config = config.device(dev.name());
+    let speed = match pinned_intensity(&dev) {
+        Some(pinned) => pinned,
+        None if !device_config.enabled => return,
+        None => {
+            let multiplier = device_config.strength.unwrap_or(config.vibration_strength);
+
+            let speed = (strength * multiplier * osc::master_intensity())
+                .clamp(0.0, battery_intensity_cap(config, &dev));
+
+            // some devices only click instead of vibrating at very low speeds
+            if speed < config.min_intensity {
+                0.0
+            } else {
+                speed
+            }
+        }
+    };
+    let attributes = dev.message_attributes();
+
+    if attributes.scalar_cmd().is_none()
+        && attributes.rotate_cmd().is_none()
+        && attributes.linear_cmd().is_none()
+    {
+        log::trace!("{} has no actuators we can drive", dev.name());
+        return;
+    }
+
+    // sending several commands at once can make them conflict on devices with more than one
+    // kind of actuator, so each one is only sent after the previous command has completed
+    if attributes.scalar_cmd().is_some() {
+        let command = vibrate_command(
+            speed,
+            dev.vibrate_attributes().len(),
+            &device_config.actuator_scales,
+        );
+        if let Err(e) = with_retries(&dev, || dev.vibrate(&command)).await {
             log::error!("Error sending vibrate command to device! {}", e);
+            stats::record_error();
             return;
         }
-    } else {
-        log::trace!("{} doesn't vibrate! This code should be updated to handle rotation and linear movement!", dev.name());
+        known_devices::remember(dev.name());
+    }
+
+    if attributes.rotate_cmd().is_some() {
+        if let Err(e) = with_retries(&dev, || dev.rotate(&rotate_command(speed))).await {
+            log::error!("Error sending rotate command to device! {}", e);
+            stats::record_error();
+            return;
+        }
+    }
+
+    if attributes.linear_cmd().is_some() {
+        if let Err(e) = with_retries(&dev, || dev.linear(&linear_command(speed))).await {
+            log::error!("Error sending linear command to device! {}", e);
+            stats::record_error();
+        }
+    }
+}
+
+/// Send a command, retrying failures `command_retries` times with a delay that doubles each time,
+/// so a flaky connection dropping the odd command doesn't make the device stutter.
+/// Each attempt gives up after `command_timeout_ms`, so a hung device can't hold up its queue
+async fn with_retries<F, E>(
+    dev: &ButtplugClientDevice,
+    mut send: impl FnMut() -> F,
+) -> Result<(), String>
+where
+    F: Future<Output = Result<(), E>>,
+    E: std::fmt::Display,
+{
+    let config = CONFIG.get().expect("config should exist");
+    let timeout = Duration::from_millis(config.command_timeout_ms);
+    let mut delay = Duration::from_millis(config.command_retry_delay_ms);
+    let mut attempt = 0;
+
+    loop {
+        let result = if timeout.is_zero() {
+            send().await.map_err(|e| e.to_string())
+        } else {
+            match tokio::time::timeout(timeout, send()).await {
+                Ok(result) => result.map_err(|e| e.to_string()),
+                Err(_) => Err(format!("timed out after {}ms", timeout.as_millis())),
+            }
+        };
+
+        match result {
+            Ok(()) => return Ok(()),
+            Err(e) if attempt < config.command_retries => {
+                log::debug!(
+                    "command to {} failed, retrying in {}ms: {e}",
+                    dev.name(),
+                    delay.as_millis()
+                );
+                sleep(delay).await;
+                delay *= 2;
+                attempt += 1;
+            }
+            Err(e) => {
+                if !dev.connected() {
+                    log::warn!(
+                        "{} looks disconnected, commands to it keep failing",
+                        dev.name()
+                    );
+                }
+                return Err(e);
+            }
+        }
     }
 }
 
-async fn stop_vibration(dev: Arc<ButtplugClientDevice>) {
-    if dev.message_attributes().scalar_cmd().is_some() {
-        if let Err(e) = dev.stop().await {
+/// Vibration for every motor of a device, each scaled separately when scales are configured
+/// for a device with several motors
+fn vibrate_command(intensity: f64, actuators: usize, scales: &[f64]) -> VibrateCommand {
+    if actuators <= 1 || scales.is_empty() {
+        return VibrateCommand::Speed(intensity);
+    }
+
+    VibrateCommand::SpeedVec(
+        (0..actuators)
+            .map(|actuator| {
+                let scale = scales.get(actuator).copied().unwrap_or(1.0);
+                (intensity * scale).clamp(0.0, 1.0)
+            })
+            .collect(),
+    )
+}
+
+/// Rotation matching a vibration intensity, so rotating devices ramp together with vibrating ones
+fn rotate_command(intensity: f64) -> RotateCommand {
+    RotateCommand::Rotate(intensity, true)
+}
+
+/// Stroke position matching a vibration intensity, reached within one poll so it keeps up with the game
+fn linear_command(intensity: f64) -> LinearCommand {
+    let config = CONFIG.get().expect("config should exist");
+    LinearCommand::Linear(config.poll_interval_ms as u32, intensity)
+}
+
+/// Fully stop a device, everything that only ends a hit uses `rest_command` to keep the idle hum
+pub async fn stop_vibration(dev: Arc<ButtplugClientDevice>) {
+    let config = CONFIG.get().expect("config should exist");
+
+    if pinned_intensity(&dev).is_some() {
+        vibrate_device(dev, 0.0).await;
+        return;
+    }
+
+    if !config.device(dev.name()).enabled {
+        return;
+    }
+
+    let attributes = dev.message_attributes();
+
+    if attributes.scalar_cmd().is_some() || attributes.rotate_cmd().is_some() {
+        if let Err(e) = with_retries(&dev, || dev.stop()).await {
             log::error!("error vibrating device: {e}");
+            stats::record_error();
         }
-    } else {
-        log::trace!("{} doesn't vibrate! This code should be updated to handle rotation and linear movement!", dev.name());
+    }
+
+    // stopping leaves linear devices wherever they are, so move them back to the start
+    if attributes.linear_cmd().is_some() {
+        if let Err(e) = with_retries(&dev, || dev.linear(&linear_command(0.0))).await {
+            log::error!("error stopping linear device: {e}");
+            stats::record_error();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_match_default_config_file() {
+        let mut config = Config::default();
+        config.apply_sections();
+
+        assert_eq!(format!("{config:?}"), format!("{:?}", default_config()));
+    }
+
+    #[test]
+    fn hitstun_flag_is_the_lowest_bit() {
+        assert!(in_hitstun(0b000001));
+        assert!(in_hitstun(0b101011));
+        assert!(!in_hitstun(0b000000));
+        assert!(!in_hitstun(0b111110));
     }
 }
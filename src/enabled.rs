@@ -0,0 +1,60 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde::{Deserialize, Serialize};
+
+/// Small file remembering whether feedback was enabled, so it survives crashes and restarts
+const STATE_PATH: &str = "./acpr_buttplug_state.toml";
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static PERSIST: AtomicBool = AtomicBool::new(false);
+
+#[derive(Serialize, Deserialize)]
+struct PersistedState {
+    enabled: bool,
+}
+
+/// Whether devices should be driven at all
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+pub fn set_enabled(enabled: bool) {
+    if ENABLED.swap(enabled, Ordering::SeqCst) == enabled {
+        return;
+    }
+
+    log::info!("feedback {}", if enabled { "enabled" } else { "disabled" });
+
+    if PERSIST.load(Ordering::SeqCst) {
+        save(enabled);
+    }
+}
+
+/// Restore the enabled state from the last session and keep it updated from now on
+pub fn restore() {
+    PERSIST.store(true, Ordering::SeqCst);
+
+    let Ok(s) = std::fs::read_to_string(STATE_PATH) else {
+        return;
+    };
+
+    match toml::from_str::<PersistedState>(&s) {
+        Ok(state) => {
+            ENABLED.store(state.enabled, Ordering::SeqCst);
+            if !state.enabled {
+                log::info!("feedback disabled since the last session");
+            }
+        }
+        Err(e) => log::error!("error reading {STATE_PATH}: {e}"),
+    }
+}
+
+fn save(enabled: bool) {
+    let result = toml::to_string(&PersistedState { enabled })
+        .map_err(|e| e.to_string())
+        .and_then(|s| std::fs::write(STATE_PATH, s).map_err(|e| e.to_string()));
+
+    if let Err(e) = result {
+        log::error!("error saving {STATE_PATH}: {e}");
+    }
+}
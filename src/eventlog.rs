@@ -0,0 +1,97 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use once_cell::sync::OnceCell;
+use windows::{
+    core::PCWSTR,
+    w,
+    Win32::{
+        Foundation::PSID,
+        System::EventLog::{
+            EventSourceHandle, RegisterEventSourceW, ReportEventW, EVENTLOG_ERROR_TYPE,
+            EVENTLOG_INFORMATION_TYPE, EVENTLOG_WARNING_TYPE, REPORT_EVENT_TYPE,
+        },
+    },
+};
+
+/// Handle of the registered event source, only set when the event log is enabled
+static EVENT_SOURCE: OnceCell<isize> = OnceCell::new();
+
+/// Register the mod as an event source, returns a logger forwarding warnings and errors to the Windows event log
+pub fn open() -> Option<EventLogger> {
+    let handle = match unsafe { RegisterEventSourceW(PCWSTR::null(), w!("acpr_buttplug")) } {
+        Ok(handle) => handle,
+        Err(e) => {
+            println!("error registering event log source: {e}");
+            return None;
+        }
+    };
+
+    EVENT_SOURCE.set(handle.0).ok()?;
+    Some(EventLogger)
+}
+
+/// Write a key event like startup or a device connecting to the event log, does nothing if it isn't enabled
+pub fn info(message: &str) {
+    report(EVENTLOG_INFORMATION_TYPE, message);
+}
+
+fn report(event_type: REPORT_EVENT_TYPE, message: &str) {
+    let Some(handle) = EVENT_SOURCE.get() else {
+        return;
+    };
+
+    let message: Vec<u16> = message.encode_utf16().chain(Some(0)).collect();
+    let strings = [PCWSTR(message.as_ptr())];
+
+    unsafe {
+        ReportEventW(
+            EventSourceHandle(*handle),
+            event_type,
+            0,
+            0,
+            PSID::default(),
+            0,
+            Some(&strings),
+            None,
+        );
+    }
+}
+
+/// Forwards warnings and errors, including panics, to the event log
+pub struct EventLogger;
+
+impl Log for EventLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= LevelFilter::Warn
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let event_type = match record.level() {
+            log::Level::Error => EVENTLOG_ERROR_TYPE,
+            _ => EVENTLOG_WARNING_TYPE,
+        };
+        report(
+            event_type,
+            &format!("{}: {}", record.target(), record.args()),
+        );
+    }
+
+    fn flush(&self) {}
+}
+
+impl simplelog::SharedLogger for EventLogger {
+    fn level(&self) -> LevelFilter {
+        LevelFilter::Warn
+    }
+
+    fn config(&self) -> Option<&simplelog::Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
@@ -1,4 +1,10 @@
 use crate::helpers::Offset;
 
+// built-in offsets, these can be overridden per game version in the offsets file
+pub const HANDLE_HIT_OFFSET: Offset = Offset::new(0x11AA80);
 pub const PLAYER_1_STATE: Offset = Offset::new(0x6AD930);
-pub const PLAYER_2_STATE: Offset = Offset::new(0x6B123C);
\ No newline at end of file
+pub const PLAYER_2_STATE: Offset = Offset::new(0x6B123C);
+
+// offsets of values inside a players state
+pub const HITSTOP_OFFSET: isize = 0xFD;
+pub const HITSTUN_FLAG_OFFSET: isize = 0xC;
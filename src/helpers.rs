@@ -1,4 +1,21 @@
-use windows::Win32::System::LibraryLoader::GetModuleHandleA;
+use std::ffi::CString;
+
+use serde::Deserialize;
+use windows::{
+    core::PCSTR,
+    Win32::{
+        System::{
+            LibraryLoader::GetModuleHandleA,
+            Memory::{
+                VirtualQuery, MEMORY_BASIC_INFORMATION, MEM_COMMIT, PAGE_EXECUTE_READ,
+                PAGE_EXECUTE_READWRITE, PAGE_EXECUTE_WRITECOPY, PAGE_GUARD, PAGE_NOACCESS,
+                PAGE_READONLY, PAGE_READWRITE, PAGE_WRITECOPY,
+            },
+            Threading::GetCurrentProcessId,
+        },
+        UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId},
+    },
+};
 
 fn get_module_base() -> isize {
     unsafe {
@@ -6,17 +23,126 @@ fn get_module_base() -> isize {
     }
 }
 
+fn get_named_module_base(module: &str) -> Option<usize> {
+    let name = CString::new(module).ok()?;
+    unsafe {
+        GetModuleHandleA(PCSTR(name.as_ptr() as *const u8))
+            .ok()
+            .map(|handle| handle.0 as usize)
+    }
+}
+
+/// Get the link timestamp from the executables PE header as a hex string,
+/// this changes with every build of the game so it identifies the game version
+pub fn get_game_version() -> String {
+    unsafe {
+        let base = get_module_base() as *const u8;
+        let pe_header_offset = base.offset(0x3C).cast::<u32>().read_unaligned();
+        // skip the "PE\0\0" signature, machine type and section count
+        let timestamp = base
+            .offset(pe_header_offset as isize + 8)
+            .cast::<u32>()
+            .read_unaligned();
+        format!("{timestamp:08X}")
+    }
+}
+
+/// Whether the window in the foreground belongs to the game
+pub fn game_has_focus() -> bool {
+    unsafe {
+        let mut process_id = 0;
+        GetWindowThreadProcessId(GetForegroundWindow(), Some(&mut process_id));
+        process_id == GetCurrentProcessId()
+    }
+}
+
+/// Read a `T` from `addr` only if the memory there is committed and readable, so a player
+/// object being freed between polls yields `None` instead of crashing the game.
+/// Heap memory usually stays committed after it's freed, this only catches released pages
+pub unsafe fn read_guarded<T: Copy>(addr: *const T) -> Option<T> {
+    if addr.is_null() {
+        return None;
+    }
+
+    let mut info = MEMORY_BASIC_INFORMATION::default();
+    let info_size = std::mem::size_of::<MEMORY_BASIC_INFORMATION>();
+    if VirtualQuery(Some(addr.cast()), &mut info, info_size) != info_size {
+        return None;
+    }
+
+    let readable = PAGE_READONLY
+        | PAGE_READWRITE
+        | PAGE_WRITECOPY
+        | PAGE_EXECUTE_READ
+        | PAGE_EXECUTE_READWRITE
+        | PAGE_EXECUTE_WRITECOPY;
+    let region_end = info.BaseAddress as usize + info.RegionSize;
+
+    if info.State != MEM_COMMIT
+        || (info.Protect & readable).0 == 0
+        || (info.Protect & (PAGE_GUARD | PAGE_NOACCESS)).0 != 0
+        || addr as usize + std::mem::size_of::<T>() > region_end
+    {
+        return None;
+    }
+
+    Some(addr.read_unaligned())
+}
+
 /// Type for finding the offset of something within a running program
-pub struct Offset(usize);
+///
+/// Deserializes from a plain number for an offset into the game executable,
+/// or from a `{ module = "name.dll", offset = ... }` table for one inside another loaded module
+#[derive(Debug, Clone, Deserialize)]
+#[serde(from = "OffsetSetting")]
+pub struct Offset {
+    offset: usize,
+    module: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OffsetSetting {
+    Main(usize),
+    InModule { module: String, offset: usize },
+}
+
+impl From<OffsetSetting> for Offset {
+    fn from(setting: OffsetSetting) -> Self {
+        match setting {
+            OffsetSetting::Main(offset) => Self::new(offset),
+            OffsetSetting::InModule { module, offset } => Self::in_module(&module, offset),
+        }
+    }
+}
 
 impl Offset {
     /// Create an [`Offset`] that calculates the offset of a programs base address
     pub const fn new(offset: usize) -> Self {
-        Self(offset)
+        Self {
+            offset,
+            module: None,
+        }
     }
 
+    /// Create an [`Offset`] from the base address of the loaded module called `module`
+    pub fn in_module(module: &str, offset: usize) -> Self {
+        Self {
+            offset,
+            module: Some(module.to_owned()),
+        }
+    }
+
+    /// The address this offset points to, when it is inside a module that isn't loaded
+    /// the base is taken as 0 so reading through [`read_guarded`] fails instead of panicking
     pub fn get_address(&self) -> usize {
-        let base = get_module_base() as usize;
-        base + self.0
+        let base = match &self.module {
+            None => get_module_base() as usize,
+            Some(module) => get_named_module_base(module).unwrap_or_else(|| {
+                log::debug!("module {module} is not loaded");
+                0
+            }),
+        };
+        base + self.offset
     }
-}
\ No newline at end of file
+}
@@ -1,16 +1,19 @@
 use std::arch::asm;
 
-use crate::helpers::Offset;
+use crate::offsets;
 use detour::RawDetour;
 use once_cell::sync::OnceCell;
-
-const HANDLE_HIT_OFFSET: Offset = Offset::new(0x11AA80);
+use windows::{
+    s,
+    Win32::System::LibraryLoader::{GetModuleHandleA, GetProcAddress},
+};
 
 static HIT_OFFSET_DETOUR: OnceCell<RawDetour> = OnceCell::new();
+static EXIT_PROCESS_DETOUR: OnceCell<RawDetour> = OnceCell::new();
 
 pub unsafe fn setup_hooks() {
     log::trace!("setting up hooks...");
-    let handle_hit_addr = HANDLE_HIT_OFFSET.get_address();
+    let handle_hit_addr = offsets::get().handle_hit.get_address();
 
     log::debug!("got handle_hit offset: {:X}", handle_hit_addr);
 
@@ -26,6 +29,9 @@ pub unsafe fn setup_hooks() {
     }
 }
 
+/// Entry point the detour jumps to, the game passes `this` in eax and two arguments on the stack.
+/// Everything the game might still expect is saved here since rust code called from it
+/// is free to clobber eax, ecx and edx
 #[naked]
 unsafe extern "C" fn handle_hit_hook() {
     asm!(
@@ -51,28 +57,70 @@ unsafe extern "C" fn handle_hit_hook() {
     )
 }
 
+// never inlined so the naked hook above always has a real thiscall function to call
 #[no_mangle]
+#[inline(never)]
 unsafe extern "thiscall" fn hit_hook(this: usize, arg2: usize, arg3: usize) {
-    use crate::dll_code::Event;
-    log::trace!("called hit_hook with arg: {:X?}", arg2);
+    use crate::dll_code::{current_hit_type, Event};
+    // neither argument is known to encode the hit type yet, so it comes from the game state in
+    // `current_hit_type` instead. they are logged to help decode them
+    log::trace!("called hit_hook with args: {:X?} {:X?}", arg2, arg3);
 
-    if let Some(channel) = crate::dll_code::HIT_CHANNEL_TX.blocking_lock().as_mut() {
-        channel.send(Event::Hit).expect("Sending Event across channel");
+    if let Some(channel) = crate::dll_code::HIT_CHANNEL_TX.get() {
+        // a panic here would unwind into the game
+        if channel.send(Event::Hit(current_hit_type())).is_err() {
+            log::error!("event loop isn't running, dropping hit");
+        }
     }
 
     let trampoline = HIT_OFFSET_DETOUR.get().unwrap().trampoline() as *const _;
 
-    // push args to stack and move arg1 into eax,
-    // then clear arguments off stack
+    // push args to stack with arg1 in eax, then clear arguments off stack.
+    // eax has to be an explicit input, writing it from inside the asm could overwrite
+    // another operand the compiler put there, and the call clobbers every caller saved register
     asm!(
-        "push {0}
-        push {1}
-        mov eax, {2}
-        call {3}
+        "push {arg3}
+        push {arg2}
+        call {trampoline}
         add esp, 0x8",
-        in(reg) arg3,
-        in(reg) arg2,
-        in(reg) this,
-        in(reg) trampoline
+        arg3 = in(reg) arg3,
+        arg2 = in(reg) arg2,
+        trampoline = in(reg) trampoline,
+        in("eax") this,
+        clobber_abi("C"),
     );
 }
+
+/// Hook `ExitProcess` so devices get stopped when the game closes. By the time the DLL is told the
+/// process is detaching every other thread is gone, including the one running the buttplug client
+pub unsafe fn setup_exit_hook() {
+    let Some(exit_process) = GetModuleHandleA(s!("kernel32.dll"))
+        .ok()
+        .and_then(|kernel32| GetProcAddress(kernel32, s!("ExitProcess")))
+    else {
+        log::error!("couldn't find ExitProcess, devices may keep vibrating after the game closes");
+        return;
+    };
+
+    let detour = EXIT_PROCESS_DETOUR.get_or_try_init(|| {
+        RawDetour::new(exit_process as *const (), exit_process_hook as *const ())
+    });
+
+    match detour.map(|detour| detour.enable()) {
+        Ok(Ok(())) => log::debug!("enabled ExitProcess hook"),
+        Ok(Err(e)) | Err(e) => log::error!(
+            "error hooking ExitProcess, devices may keep vibrating after the game closes: {e}"
+        ),
+    }
+}
+
+unsafe extern "system" fn exit_process_hook(exit_code: u32) {
+    type ExitProcess = unsafe extern "system" fn(u32);
+
+    log::info!("game is exiting, stopping all devices");
+    // the stops are sent from a fresh thread, the game may call this from inside anything
+    let _ = std::thread::spawn(crate::dll_code::before_exit).join();
+
+    let trampoline = EXIT_PROCESS_DETOUR.get().unwrap().trampoline() as *const ();
+    std::mem::transmute::<_, ExitProcess>(trampoline)(exit_code);
+}
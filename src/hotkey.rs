@@ -0,0 +1,37 @@
+use windows::Win32::UI::Input::KeyboardAndMouse::{GetAsyncKeyState, VK_F1};
+
+/// A key checked on every poll, works while the game or any other window has focus
+pub struct Hotkey {
+    virtual_key: i32,
+    was_down: bool,
+}
+
+impl Hotkey {
+    /// Parse a key name like "F8", "P" or "0", returns `None` for unsupported keys
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_uppercase();
+
+        let virtual_key = match name.strip_prefix('F').map(str::parse::<i32>) {
+            Some(Ok(number @ 1..=24)) => VK_F1.0 as i32 + number - 1,
+            _ => match name.as_bytes() {
+                // letters and digits use their ascii code as the virtual key code
+                [key] if key.is_ascii_alphanumeric() => *key as i32,
+                _ => return None,
+            },
+        };
+
+        Some(Self {
+            virtual_key,
+            was_down: false,
+        })
+    }
+
+    /// Whether the key went down since the last check, holding it only counts once
+    pub fn pressed(&mut self) -> bool {
+        // the most significant bit is set while the key is held
+        let down = unsafe { GetAsyncKeyState(self.virtual_key) } < 0;
+        let pressed = down && !self.was_down;
+        self.was_down = down;
+        pressed
+    }
+}
@@ -0,0 +1,59 @@
+use std::{collections::BTreeSet, sync::Mutex};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+/// Small file remembering the devices that have been used before, by the name they report
+const KNOWN_DEVICES_PATH: &str = "./acpr_buttplug_known_devices.toml";
+
+static KNOWN_DEVICES: Lazy<Mutex<BTreeSet<String>>> = Lazy::new(|| Mutex::new(BTreeSet::new()));
+
+#[derive(Default, Serialize, Deserialize)]
+struct PersistedDevices {
+    devices: BTreeSet<String>,
+}
+
+/// Load the devices remembered from previous sessions
+pub fn load() {
+    let Ok(s) = std::fs::read_to_string(KNOWN_DEVICES_PATH) else {
+        return;
+    };
+
+    match toml::from_str::<PersistedDevices>(&s) {
+        Ok(persisted) => {
+            log::debug!("{} known devices", persisted.devices.len());
+            if let Ok(mut known) = KNOWN_DEVICES.lock() {
+                *known = persisted.devices;
+            }
+        }
+        Err(e) => log::error!("error reading {KNOWN_DEVICES_PATH}: {e}"),
+    }
+}
+
+/// Whether a device with this name has been used before
+pub fn is_known(name: &str) -> bool {
+    KNOWN_DEVICES
+        .lock()
+        .map_or(false, |known| known.contains(name))
+}
+
+/// Remember a device that was used successfully, saving the file the first time it is seen
+pub fn remember(name: &str) {
+    let devices = match KNOWN_DEVICES.lock() {
+        Ok(mut known) if !known.contains(name) => {
+            known.insert(name.to_owned());
+            known.clone()
+        }
+        _ => return,
+    };
+
+    log::info!("remembering {name} as a known device");
+
+    let result = toml::to_string(&PersistedDevices { devices })
+        .map_err(|e| e.to_string())
+        .and_then(|s| std::fs::write(KNOWN_DEVICES_PATH, s).map_err(|e| e.to_string()));
+
+    if let Err(e) = result {
+        log::error!("error saving {KNOWN_DEVICES_PATH}: {e}");
+    }
+}
@@ -15,26 +15,47 @@ use windows::{
         System::{
             LibraryLoader::{GetProcAddress, LoadLibraryW},
             SystemInformation::GetSystemDirectoryW,
-            SystemServices::DLL_PROCESS_ATTACH,
+            SystemServices::{DLL_PROCESS_ATTACH, DLL_PROCESS_DETACH},
         },
     },
 };
 
+mod battery;
+mod connection;
+mod console;
+mod curve;
+mod dispatch;
 mod dll_code;
+mod enabled;
+mod eventlog;
 mod helpers;
 mod hooks;
+mod hotkey;
+mod known_devices;
+mod light;
 mod global;
+mod offsets;
+mod osc;
+mod overlay;
+mod pattern;
+mod simulate;
+mod stats;
+mod telemetry;
 
 #[no_mangle]
 extern "system" fn DllMain(
     _dll_module: HINSTANCE,
     call_reason: u32,
-    _reserved: *mut c_void,
+    reserved: *mut c_void,
 ) -> BOOL {
     match call_reason {
         DLL_PROCESS_ATTACH => {
             std::thread::spawn(dll_code::initialize);
         }
+        DLL_PROCESS_DETACH => {
+            // a non-null reserved pointer means the process is exiting rather than unloading the DLL
+            dll_code::shutdown(!reserved.is_null());
+        }
         _ => (),
     };
 
@@ -106,3 +127,26 @@ unsafe fn get_dinput8_handle() -> HINSTANCE {
 
 #[no_mangle]
 pub unsafe extern "system" fn ShowJoyCPL(_hwnd: windows::Win32::Foundation::HWND) {}
+
+/// Push a hit from another mod, vibrating at `intensity` from 0.0 to 1.0 for `external_hit_ms`.
+///
+/// Declared in C as `bool acpr_buttplug_push_hit(double intensity);` using the cdecl calling convention,
+/// it only queues the hit so it never blocks and is safe to call from any thread, including from inside
+/// a game hook. Returns `false` if the hit was dropped because the intensity isn't a number or the mod
+/// hasn't finished starting up.
+#[no_mangle]
+pub extern "C" fn acpr_buttplug_push_hit(intensity: f64) -> bool {
+    if intensity.is_nan() {
+        return false;
+    }
+
+    let Some(channel) = dll_code::CHANNEL_TX.get() else {
+        return false;
+    };
+
+    channel
+        .send(dll_code::Event::ExternalHit {
+            intensity: intensity.clamp(0.0, 1.0),
+        })
+        .is_ok()
+}
@@ -0,0 +1,82 @@
+use std::{io::Write, time::Duration};
+
+use serde::Deserialize;
+use serialport::SerialPort;
+
+/// How light levels are encoded on the serial port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightProtocol {
+    /// A single byte per update, for microcontroller driven lights
+    Raw,
+    /// A DMX universe framed for Enttec DMX USB Pro compatible interfaces
+    EnttecPro,
+}
+
+/// A light driven over a serial port, flashing along with the vibration intensity
+pub struct Light {
+    port: Box<dyn SerialPort>,
+    protocol: LightProtocol,
+    channel: u16,
+    last_level: Option<u8>,
+}
+
+impl Light {
+    pub fn open(
+        path: &str,
+        baud_rate: u32,
+        protocol: LightProtocol,
+        channel: u16,
+    ) -> serialport::Result<Self> {
+        let port = serialport::new(path, baud_rate)
+            .timeout(Duration::from_millis(50))
+            .open()?;
+
+        Ok(Self {
+            port,
+            protocol,
+            channel: channel.clamp(1, 512),
+            last_level: None,
+        })
+    }
+
+    /// Set the light to an intensity between 0.0 and 1.0, only writing when the level changes
+    pub fn set_intensity(&mut self, intensity: f64) {
+        let level = (intensity.clamp(0.0, 1.0) * u8::MAX as f64).round() as u8;
+        if self.last_level == Some(level) {
+            return;
+        }
+
+        let result = match self.protocol {
+            LightProtocol::Raw => self.port.write_all(&[level]),
+            LightProtocol::EnttecPro => self.port.write_all(&enttec_pro_frame(self.channel, level)),
+        };
+
+        match result {
+            Ok(()) => self.last_level = Some(level),
+            Err(e) => log::error!("error writing light level: {e}"),
+        }
+    }
+}
+
+/// Build an "Output Only Send DMX Packet" message setting `channel` to `level`
+fn enttec_pro_frame(channel: u16, level: u8) -> Vec<u8> {
+    const START_OF_MESSAGE: u8 = 0x7E;
+    const SEND_DMX_LABEL: u8 = 6;
+    const END_OF_MESSAGE: u8 = 0xE7;
+
+    // DMX start code followed by every channel up to the one we drive
+    let mut data = vec![0u8; channel as usize + 1];
+    data[channel as usize] = level;
+
+    let length = data.len() as u16;
+    let mut frame = vec![
+        START_OF_MESSAGE,
+        SEND_DMX_LABEL,
+        (length & 0xFF) as u8,
+        (length >> 8) as u8,
+    ];
+    frame.extend(data);
+    frame.push(END_OF_MESSAGE);
+    frame
+}
@@ -0,0 +1,185 @@
+use std::{collections::HashMap, path::Path};
+
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+
+use crate::{
+    global::{
+        HANDLE_HIT_OFFSET, HITSTOP_OFFSET, HITSTUN_FLAG_OFFSET, PLAYER_1_STATE, PLAYER_2_STATE,
+    },
+    helpers::{get_game_version, Offset},
+};
+
+/// File with a table of [`GameOffsets`] per game version, the detected version is logged on startup
+/// ```toml
+/// ["4F5A1C2B"]
+/// handle_hit = 0x11AA80
+/// player1_state = 0x6AD930
+/// player2_state = 0x6B123C
+/// hitstop = 0xFD
+/// hitstun_flag = 0xC
+/// # offsets can also be inside another loaded module instead of the game executable
+/// paused = { module = "example.dll", offset = 0x1234 }
+/// ```
+const OFFSETS_PATH: &str = "./acpr_buttplug_offsets.toml";
+
+/// The same offsets as JSON, for sharing offset files made by other tools. JSON has no hex numbers,
+/// so offsets are written in decimal. Either a table per game version like the TOML file,
+/// or the named entries on their own to use them for every version
+/// ```json
+/// {
+///     "handle_hit": 1157760,
+///     "player1_state": 7002416,
+///     "player2_state": 7017020,
+///     "hitstop": 253,
+///     "hitstun_flag": 12
+/// }
+/// ```
+const OFFSETS_JSON_PATH: &str = "./offsets.json";
+
+/// Memory offsets for one version of the game, anything left out uses the built-in value
+#[derive(Debug, Clone, Deserialize)]
+// unknown names are rejected, otherwise a table of versions could be mistaken for a single entry
+#[serde(default, deny_unknown_fields)]
+pub struct GameOffsets {
+    pub handle_hit: Offset,
+    pub player1_state: Offset,
+    pub player2_state: Offset,
+    /// Offset of the hitstop byte inside a players state
+    pub hitstop: isize,
+    /// Offset of the byte holding the hitstun flag inside a players state
+    pub hitstun_flag: isize,
+    /// Offset of the health value inside a players state
+    pub health: Option<isize>,
+    /// Offset of the remaining hitstun inside a players state
+    pub hitstun: Option<isize>,
+    /// Offset of the combo counter inside a players state
+    pub combo: Option<isize>,
+    /// Offset of the horizontal position inside a players state
+    pub position_x: Option<isize>,
+    /// Offset of a byte inside a players state that is nonzero while they perform a super
+    pub super_flag: Option<isize>,
+    /// Offset of a byte inside a players state that is nonzero while they are blocking
+    pub blocking: Option<isize>,
+    /// Offset of the tension gauge inside a players state
+    pub tension: Option<isize>,
+    /// Offset of a byte inside a players state that is nonzero while they are being thrown
+    pub thrown: Option<isize>,
+    /// Offset of a byte inside a players state that is nonzero on an Instant Block
+    pub instant_block: Option<isize>,
+    /// Offset of a byte inside a players state that is nonzero while they use Faultless Defense
+    pub faultless_defense: Option<isize>,
+    /// Offset of a byte inside a players state that is nonzero while one of their attacks is active
+    pub attacking: Option<isize>,
+    /// Offset of a byte inside a players state that is nonzero while they are knocked down
+    pub knockdown: Option<isize>,
+    /// Offset of a byte inside a players state that is nonzero during their super flash
+    pub super_flash: Option<isize>,
+    /// Offset of a byte inside a players state counting the rounds they have won in the current match
+    pub rounds_won: Option<isize>,
+    /// Offset of a byte from the games base address that changes on every round and match transition
+    pub round_state: Option<Offset>,
+    /// Offset of a byte from the games base address that is nonzero while the game is paused
+    pub paused: Option<Offset>,
+}
+
+impl Default for GameOffsets {
+    fn default() -> Self {
+        Self {
+            handle_hit: HANDLE_HIT_OFFSET,
+            player1_state: PLAYER_1_STATE,
+            player2_state: PLAYER_2_STATE,
+            hitstop: HITSTOP_OFFSET,
+            hitstun_flag: HITSTUN_FLAG_OFFSET,
+            health: None,
+            hitstun: None,
+            combo: None,
+            position_x: None,
+            super_flag: None,
+            blocking: None,
+            tension: None,
+            thrown: None,
+            instant_block: None,
+            faultless_defense: None,
+            attacking: None,
+            knockdown: None,
+            super_flash: None,
+            rounds_won: None,
+            round_state: None,
+            paused: None,
+        }
+    }
+}
+
+static OFFSETS: OnceCell<GameOffsets> = OnceCell::new();
+
+/// Offsets for the running game version, loading them on first use
+pub fn get() -> &'static GameOffsets {
+    OFFSETS.get_or_init(load_offsets)
+}
+
+/// Contents of an offsets file
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OffsetsFile {
+    /// Offsets per game version
+    Versions(HashMap<String, GameOffsets>),
+    /// Offsets used whatever the game version is
+    Any(GameOffsets),
+}
+
+/// Look up the offsets for the running game version in the offsets file, preferring the JSON file,
+/// falling back to the built-in offsets when there is no matching entry
+fn load_offsets() -> GameOffsets {
+    let version = get_game_version();
+    log::info!("detected game version {version}");
+
+    let (path, file) = if Path::new(OFFSETS_JSON_PATH).exists() {
+        (
+            OFFSETS_JSON_PATH,
+            read_offsets_file(OFFSETS_JSON_PATH, |s| {
+                serde_json::from_str(s).map_err(|e| e.to_string())
+            }),
+        )
+    } else if Path::new(OFFSETS_PATH).exists() {
+        (
+            OFFSETS_PATH,
+            read_offsets_file(OFFSETS_PATH, |s| {
+                toml::from_str(s).map_err(|e| e.to_string())
+            }),
+        )
+    } else {
+        log::debug!("no offsets file found, using built-in offsets");
+        return GameOffsets::default();
+    };
+
+    match file {
+        Ok(OffsetsFile::Versions(mut versions)) => match versions.remove(&version) {
+            Some(offsets) => {
+                log::info!("using offsets for version {version} from {path}");
+                offsets
+            }
+            None => {
+                log::warn!("no offsets for version {version} in {path}, using built-in offsets");
+                GameOffsets::default()
+            }
+        },
+        Ok(OffsetsFile::Any(offsets)) => {
+            log::info!("using offsets from {path}");
+            offsets
+        }
+        Err(e) => {
+            log::error!("error reading {path}: {e}, using built-in offsets");
+            GameOffsets::default()
+        }
+    }
+}
+
+fn read_offsets_file(
+    path: &str,
+    parse: impl FnOnce(&str) -> Result<OffsetsFile, String>,
+) -> Result<OffsetsFile, String> {
+    std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|s| parse(&s))
+}
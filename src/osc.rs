@@ -0,0 +1,131 @@
+use std::{
+    net::UdpSocket,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use once_cell::sync::Lazy;
+use rosc::{OscMessage, OscPacket, OscType};
+use serde::Deserialize;
+
+/// Master intensity scaler set from an external controller, applied on top of all other scaling
+static MASTER_INTENSITY: Lazy<AtomicU64> = Lazy::new(|| AtomicU64::new(1.0f64.to_bits()));
+
+pub fn master_intensity() -> f64 {
+    f64::from_bits(MASTER_INTENSITY.load(Ordering::Relaxed))
+}
+
+fn set_master_intensity(value: f64) {
+    let value = value.clamp(0.0, 1.0);
+    MASTER_INTENSITY.store(value.to_bits(), Ordering::Relaxed);
+    log::debug!("master intensity set to {value}");
+}
+
+/// Listen for OSC messages on `port` and use floats sent to `address` as the master intensity
+pub fn spawn_master_intensity_listener(port: u16, address: &'static str) {
+    let socket = match UdpSocket::bind(("127.0.0.1", port)) {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::error!("error binding OSC input port {port}: {e}");
+            return;
+        }
+    };
+
+    log::info!("listening for master intensity on OSC port {port} at {address}");
+
+    std::thread::spawn(move || {
+        let mut buffer = [0u8; rosc::decoder::MTU];
+        loop {
+            let size = match socket.recv(&mut buffer) {
+                Ok(size) => size,
+                Err(e) => {
+                    log::error!("error receiving OSC message: {e}");
+                    continue;
+                }
+            };
+
+            match rosc::decoder::decode_udp(&buffer[..size]) {
+                Ok((_, packet)) => handle_packet(packet, address),
+                Err(e) => log::debug!("invalid OSC packet: {e:?}"),
+            }
+        }
+    });
+}
+
+fn handle_packet(packet: OscPacket, address: &str) {
+    match packet {
+        OscPacket::Message(message) if message.addr == address => {
+            let value = match message.args.first() {
+                Some(OscType::Float(value)) => *value as f64,
+                Some(OscType::Double(value)) => *value,
+                // MIDI bridges often forward CC values as 0-127 ints
+                Some(OscType::Int(value)) => *value as f64 / 127.0,
+                _ => return,
+            };
+            set_master_intensity(value);
+        }
+        OscPacket::Message(_) => {}
+        OscPacket::Bundle(bundle) => {
+            for packet in bundle.content {
+                handle_packet(packet, address);
+            }
+        }
+    }
+}
+
+/// Where the intensity is sent each tick, set through the `[osc]` config section
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct OscOutputConfig {
+    pub address: String,
+    pub port: u16,
+    /// OSC path the intensity is sent to, like an avatar parameter
+    pub parameter: String,
+}
+
+impl Default for OscOutputConfig {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1".into(),
+            // VRChat listens for OSC input here
+            port: 9000,
+            parameter: "/avatar/parameters/ACPRIntensity".into(),
+        }
+    }
+}
+
+/// Sends the vibration intensity as an OSC float alongside the devices, for avatar parameters and the like
+pub struct OscOutput {
+    socket: UdpSocket,
+    parameter: String,
+}
+
+impl OscOutput {
+    pub fn open(config: &OscOutputConfig) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.connect((config.address.as_str(), config.port))?;
+        // a slow or missing receiver must never hold up polling
+        socket.set_nonblocking(true)?;
+
+        Ok(Self {
+            socket,
+            parameter: config.parameter.clone(),
+        })
+    }
+
+    /// Send an intensity between 0.0 and 1.0, errors are only logged
+    pub fn set_intensity(&self, intensity: f64) {
+        let packet = OscPacket::Message(OscMessage {
+            addr: self.parameter.clone(),
+            args: vec![OscType::Float(intensity.clamp(0.0, 1.0) as f32)],
+        });
+
+        let result = rosc::encoder::encode(&packet)
+            .map_err(|e| format!("{e:?}"))
+            .and_then(|buffer| self.socket.send(&buffer).map_err(|e| e.to_string()));
+
+        if let Err(e) = result {
+            // nothing listening is normal when the receiver isn't running, so keep it out of the log
+            log::debug!("error sending OSC intensity: {e}");
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use futures::SinkExt;
+use serde::Serialize;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+/// What overlays receive as JSON whenever any of it changes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct OverlayState {
+    pub intensity: f64,
+    pub hitstop: u8,
+    pub device_count: usize,
+}
+
+/// A local WebSocket server broadcasting the live intensity, for stream overlays like an OBS browser source
+pub struct Overlay {
+    state: watch::Sender<OverlayState>,
+}
+
+impl Overlay {
+    /// Start listening on `port` on localhost, clients are served in the background
+    pub async fn start(port: u16) -> std::io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+        let (state, receiver) = watch::channel(OverlayState::default());
+
+        tokio::spawn(accept_clients(listener, receiver));
+
+        Ok(Self { state })
+    }
+
+    /// Publish the current state, clients are only sent anything when it changed
+    pub fn update(&self, state: OverlayState) {
+        self.state.send_if_modified(|current| {
+            let changed = *current != state;
+            *current = state;
+            changed
+        });
+    }
+}
+
+async fn accept_clients(listener: TcpListener, receiver: watch::Receiver<OverlayState>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, address)) => {
+                log::debug!("overlay client connected from {address}");
+                tokio::spawn(serve_client(stream, receiver.clone()));
+            }
+            Err(e) => log::debug!("error accepting overlay client: {e}"),
+        }
+    }
+}
+
+/// Send the state to one client until it disconnects, starting with the current state
+async fn serve_client(stream: TcpStream, mut receiver: watch::Receiver<OverlayState>) {
+    let mut socket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            log::debug!("overlay websocket handshake failed: {e}");
+            return;
+        }
+    };
+
+    loop {
+        let state = *receiver.borrow_and_update();
+        let json = match serde_json::to_string(&state) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("error serializing overlay state: {e}");
+                return;
+            }
+        };
+
+        if socket.send(Message::Text(json)).await.is_err() {
+            log::debug!("overlay client disconnected");
+            return;
+        }
+
+        if receiver.changed().await.is_err() {
+            return;
+        }
+    }
+}
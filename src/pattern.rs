@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// A single step of a vibration pattern
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct PatternStep {
+    pub intensity: f64,
+    pub duration_ms: u64,
+}
+
+/// Plays back a pattern of steps over time, starting when it is created
+pub struct PatternPlayer {
+    steps: Vec<PatternStep>,
+    started: Instant,
+    priority: u8,
+}
+
+impl PatternPlayer {
+    pub fn new(steps: Vec<PatternStep>, priority: u8) -> Self {
+        Self {
+            steps,
+            started: Instant::now(),
+            priority,
+        }
+    }
+
+    pub fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    /// Intensity at the current point in the pattern, `None` once it has finished
+    pub fn intensity(&self) -> Option<f64> {
+        let mut elapsed = self.started.elapsed();
+
+        for step in &self.steps {
+            let duration = Duration::from_millis(step.duration_ms);
+            if elapsed < duration {
+                return Some(step.intensity);
+            }
+            elapsed -= duration;
+        }
+
+        None
+    }
+}
+
+/// Pulses of `pulse_ms` on and off, fading from `strength` down to nothing over `duration_ms`
+pub fn decaying_pulses(strength: f64, duration_ms: u64, pulse_ms: u64) -> Vec<PatternStep> {
+    let pulse_ms = pulse_ms.max(1);
+    let pulses = (duration_ms / (pulse_ms * 2)).max(1);
+
+    (0..pulses)
+        .flat_map(|pulse| {
+            let intensity = strength * (1.0 - pulse as f64 / pulses as f64);
+            [
+                PatternStep {
+                    intensity,
+                    duration_ms: pulse_ms,
+                },
+                PatternStep {
+                    intensity: 0.0,
+                    duration_ms: pulse_ms,
+                },
+            ]
+        })
+        .collect()
+}
+
+/// Start a new pattern unless a higher priority one is still playing
+pub fn start_pattern(current: &mut Option<PatternPlayer>, steps: Vec<PatternStep>, priority: u8) {
+    if let Some(playing) = current {
+        if playing.priority > priority && playing.intensity().is_some() {
+            log::debug!(
+                "pattern with priority {priority} ducked by a pattern with priority {}",
+                playing.priority
+            );
+            return;
+        }
+    }
+
+    *current = Some(PatternPlayer::new(steps, priority));
+}
+
+/// Combine continuous feedback with a playing pattern, while the higher priority layer
+/// is active the lower priority one is scaled down by `ducking`
+pub fn combine_layers(
+    continuous: f64,
+    continuous_priority: u8,
+    pattern: Option<(f64, u8)>,
+    ducking: f64,
+) -> f64 {
+    match pattern {
+        None => continuous,
+        Some((level, priority)) if priority >= continuous_priority => {
+            level.max(continuous * ducking)
+        }
+        Some((level, _)) if continuous > 0.0 => continuous.max(level * ducking),
+        Some((level, _)) => level,
+    }
+}
@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+/// One part of the simulated script, the hitstop moves linearly from `from` to `to`
+/// as fractions of the peak hitstop over `duration_ms`
+struct Phase {
+    from: f64,
+    to: f64,
+    duration_ms: u64,
+}
+
+const fn phase(from: f64, to: f64, duration_ms: u64) -> Phase {
+    Phase {
+        from,
+        to,
+        duration_ms,
+    }
+}
+
+/// Ramp up, hold, ramp down, then a few short spikes so separate hits can be felt too
+const SCRIPT: &[Phase] = &[
+    phase(0.0, 1.0, 3000),
+    phase(1.0, 1.0, 2000),
+    phase(1.0, 0.0, 3000),
+    phase(0.0, 0.0, 1000),
+    phase(1.0, 1.0, 150),
+    phase(0.0, 0.0, 500),
+    phase(0.6, 0.6, 150),
+    phase(0.0, 0.0, 500),
+    phase(0.3, 0.3, 150),
+    phase(0.0, 0.0, 1500),
+];
+
+/// Generates hitstop values in place of reading them from the game, looping over a fixed script
+pub struct Simulation {
+    started: Instant,
+    peak: f64,
+}
+
+impl Simulation {
+    /// `peak` is the highest raw hitstop the script reaches
+    pub fn new(peak: u8) -> Self {
+        Self {
+            started: Instant::now(),
+            peak: peak as f64,
+        }
+    }
+
+    /// The raw hitstop for the current moment of the script
+    pub fn hitstop(&self) -> u8 {
+        (hitstop_fraction(self.started.elapsed()) * self.peak).round() as u8
+    }
+}
+
+/// Where in the script `elapsed` lands, as a fraction of the peak hitstop
+fn hitstop_fraction(elapsed: Duration) -> f64 {
+    let length: u64 = SCRIPT.iter().map(|phase| phase.duration_ms).sum();
+    let mut position = elapsed.as_millis() as u64 % length;
+
+    for phase in SCRIPT {
+        if position < phase.duration_ms {
+            let progress = position as f64 / phase.duration_ms as f64;
+            return phase.from + (phase.to - phase.from) * progress;
+        }
+        position -= phase.duration_ms;
+    }
+
+    0.0
+}
@@ -0,0 +1,64 @@
+use std::{collections::BTreeSet, sync::Mutex, time::Duration};
+
+use once_cell::sync::Lazy;
+
+/// Totals for the current session, logged as a summary on shutdown
+#[derive(Debug, Default)]
+struct SessionStats {
+    hits: u64,
+    vibration_time: Duration,
+    peak_intensity: f64,
+    devices: BTreeSet<String>,
+    errors: u64,
+}
+
+static STATS: Lazy<Mutex<SessionStats>> = Lazy::new(|| Mutex::new(SessionStats::default()));
+
+pub fn record_hits(hits: usize) {
+    if let Ok(mut stats) = STATS.lock() {
+        stats.hits += hits as u64;
+    }
+}
+
+/// Record that devices were vibrating at `intensity` for `duration`
+pub fn record_vibration(intensity: f64, duration: Duration) {
+    if let Ok(mut stats) = STATS.lock() {
+        stats.vibration_time += duration;
+        stats.peak_intensity = stats.peak_intensity.max(intensity);
+    }
+}
+
+pub fn record_device(name: &str) {
+    if let Ok(mut stats) = STATS.lock() {
+        stats.devices.insert(name.to_string());
+    }
+}
+
+pub fn record_error() {
+    if let Ok(mut stats) = STATS.lock() {
+        stats.errors += 1;
+    }
+}
+
+pub fn log_summary() {
+    // never wait for the lock, this can run from DllMain while the loader lock is held
+    let Ok(stats) = STATS.try_lock() else {
+        log::warn!("session stats unavailable");
+        return;
+    };
+
+    let devices = if stats.devices.is_empty() {
+        "none".to_string()
+    } else {
+        stats.devices.iter().cloned().collect::<Vec<_>>().join(", ")
+    };
+
+    log::info!(
+        "session summary: {} hits, {:.1}s of vibration, peak intensity {:.2}, devices used: {}, {} errors",
+        stats.hits,
+        stats.vibration_time.as_secs_f64(),
+        stats.peak_intensity,
+        devices,
+        stats.errors
+    );
+}
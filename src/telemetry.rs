@@ -0,0 +1,157 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+
+/// How often buffered rows are written out, so a crash loses at most this much data
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+const HEADER: &str =
+    "timestamp_ms,hitstop,p1_hitstop,p2_hitstop,in_hitstun,intensity,device_count,devices";
+
+/// When a telemetry row gets recorded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TelemetryMode {
+    Off,
+    /// A row for every poll of the game state
+    Tick,
+    /// A row only when a new hit lands
+    Hit,
+}
+
+/// Appends hit and intensity data to a CSV file for later analysis
+pub struct Telemetry {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    last_flush: Instant,
+    /// Size of the file so far, including rows still buffered
+    size: u64,
+    /// Once the file grows past this it's moved aside and a new one is started, 0 for no limit
+    max_size: u64,
+}
+
+impl Telemetry {
+    /// Open the CSV file for appending, writing a header if the file is new.
+    /// When the file grows past `max_size` bytes it is renamed to `<name>.old.csv`, replacing the previous one.
+    /// A file written with different columns is moved aside the same way, so its rows never mix with new ones
+    pub fn open(path: &Path, max_size: u64) -> std::io::Result<Self> {
+        if let Some(header) = read_header(path) {
+            if header != HEADER {
+                let old_path = path.with_extension("old.csv");
+                std::fs::rename(path, &old_path)?;
+                log::info!(
+                    "telemetry file has different columns, moved to {}",
+                    old_path.display()
+                );
+            }
+        }
+
+        let (writer, size) = open_file(path)?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            writer,
+            last_flush: Instant::now(),
+            size,
+            max_size,
+        })
+    }
+
+    /// Record a row, `hitstop` is the value driving the vibration and `player_hitstop` each players own,
+    /// left empty for a player that doesn't exist. `devices` holds the name and intensity of every device
+    /// that was driven this tick
+    pub fn record(
+        &mut self,
+        hitstop: u8,
+        player_hitstop: [Option<u8>; 2],
+        in_hitstun: bool,
+        intensity: f64,
+        devices: &[(String, f64)],
+    ) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let [p1_hitstop, p2_hitstop] = player_hitstop.map(|hitstop| {
+            hitstop
+                .map(|hitstop| hitstop.to_string())
+                .unwrap_or_default()
+        });
+        let device_count = devices.len();
+        // one field for every device, so the columns stay the same as devices come and go
+        let device_values = devices
+            .iter()
+            .map(|(name, intensity)| format!("{name}={intensity:.4}"))
+            .collect::<Vec<_>>()
+            .join(";")
+            .replace('"', "\"\"");
+
+        let row = format!(
+            "{timestamp},{hitstop},{p1_hitstop},{p2_hitstop},{},{intensity:.4},{device_count},\"{device_values}\"",
+            in_hitstun as u8
+        );
+        if let Err(e) = writeln!(self.writer, "{row}") {
+            log::error!("error writing telemetry: {e}");
+            return;
+        }
+        self.size += row.len() as u64 + 1;
+
+        if self.max_size > 0 && self.size >= self.max_size {
+            self.rotate();
+        } else if self.last_flush.elapsed() >= FLUSH_INTERVAL {
+            if let Err(e) = self.writer.flush() {
+                log::error!("error flushing telemetry: {e}");
+            }
+            self.last_flush = Instant::now();
+        }
+    }
+
+    /// Move the full file aside and continue in a new one
+    fn rotate(&mut self) {
+        if let Err(e) = self.writer.flush() {
+            log::error!("error flushing telemetry: {e}");
+        }
+
+        let old_path = self.path.with_extension("old.csv");
+        let result = std::fs::rename(&self.path, &old_path).and_then(|_| open_file(&self.path));
+        match result {
+            Ok((writer, size)) => {
+                log::info!("telemetry file full, moved to {}", old_path.display());
+                self.writer = writer;
+                self.size = size;
+                self.last_flush = Instant::now();
+            }
+            Err(e) => {
+                log::error!("error rotating telemetry file, no longer limiting its size: {e}");
+                self.max_size = 0;
+            }
+        }
+    }
+}
+
+/// First line of an existing file, `None` if there is no file or it is empty
+fn read_header(path: &Path) -> Option<String> {
+    let file = File::open(path).ok()?;
+    let mut header = String::new();
+    BufReader::new(file).read_line(&mut header).ok()?;
+
+    (!header.is_empty()).then(|| header.trim_end().to_string())
+}
+
+fn open_file(path: &Path) -> std::io::Result<(BufWriter<File>, u64)> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut size = file.metadata()?.len();
+
+    let mut writer = BufWriter::new(file);
+    if size == 0 {
+        writeln!(writer, "{HEADER}")?;
+        size = HEADER.len() as u64 + 1;
+    }
+
+    Ok((writer, size))
+}